@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS share (
+    user_id BIGINT PRIMARY KEY,
+    shares REAL NOT NULL DEFAULT 0,
+    generators INTEGER NOT NULL DEFAULT 0,
+    prestige_points INTEGER NOT NULL DEFAULT 0,
+    prestige_count INTEGER NOT NULL DEFAULT 0,
+    collection_time TIMESTAMPTZ,
+    generation_time TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- one bit per entry in `PERKS`, indexed the same way
+    perks BIT VARYING NOT NULL DEFAULT B'00000'
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS guild_config (
+    guild_id BIGINT PRIMARY KEY,
+    event_report_channel BIGINT,
+    event_ping_role BIGINT,
+    markov_channel BIGINT,
+    voice_alert_user BIGINT
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS markov_learn_channel (
+    channel_id BIGINT PRIMARY KEY,
+    guild_id BIGINT NOT NULL
+);
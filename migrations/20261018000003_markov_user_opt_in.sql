@@ -0,0 +1,3 @@
+CREATE TABLE IF NOT EXISTS markov_user_opt_in (
+    user_id BIGINT PRIMARY KEY
+);
@@ -0,0 +1,2 @@
+ALTER TABLE guild_config
+    ADD COLUMN IF NOT EXISTS markov_links TEXT CHECK (markov_links IN ('keep', 'strip', 'defang'));
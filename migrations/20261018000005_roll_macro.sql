@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS roll_macro (
+    user_id BIGINT NOT NULL,
+    name TEXT NOT NULL,
+    expression TEXT NOT NULL,
+    PRIMARY KEY (user_id, name)
+);
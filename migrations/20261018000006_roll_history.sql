@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS roll_history (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    guild_id BIGINT,
+    channel_id BIGINT NOT NULL,
+    expression TEXT NOT NULL,
+    seed BIGINT NOT NULL,
+    total DOUBLE PRECISION NOT NULL,
+    -- sides and first roll of each die, indexed the same way
+    die_sides INTEGER[] NOT NULL,
+    die_values INTEGER[] NOT NULL,
+    rolled_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS roll_history_user_id ON roll_history (user_id, rolled_at DESC);
@@ -0,0 +1,22 @@
+ALTER TABLE share
+    -- each perk a user has and its level, by the `id` in `PERKS`
+    ADD COLUMN perk_ids TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN perk_levels INTEGER[] NOT NULL DEFAULT '{}',
+    -- every perk needs exactly one level
+    ADD CONSTRAINT share_perk_levels_match CHECK (cardinality(perk_ids) = cardinality(perk_levels));
+
+-- perks used to be one bit each, in the order they were in `PERKS`
+UPDATE share
+SET (perk_ids, perk_levels) = (
+    SELECT COALESCE(array_agg(perk.id ORDER BY perk.bit), '{}'), COALESCE(array_agg(1), '{}')
+    FROM (VALUES
+        (0, 'electric_love'),
+        (1, 'prism_cube'),
+        (2, 'spiral'),
+        (3, 'dance_robot_dance'),
+        (4, 'world_is_mine')
+    ) AS perk(bit, id)
+    WHERE perk.bit < length(share.perks) AND get_bit(share.perks, perk.bit) = 1
+);
+
+ALTER TABLE share DROP COLUMN perks;
@@ -0,0 +1,2 @@
+-- when perks were last respecced, for the cooldown
+ALTER TABLE share ADD COLUMN respec_time TIMESTAMPTZ;
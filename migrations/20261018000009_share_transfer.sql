@@ -0,0 +1,22 @@
+ALTER TABLE share ADD COLUMN joined_at TIMESTAMPTZ;
+-- everyone from before this was tracked has been playing long enough
+UPDATE share SET joined_at = 'epoch';
+ALTER TABLE share
+    ALTER COLUMN joined_at SET NOT NULL,
+    ALTER COLUMN joined_at SET DEFAULT now();
+
+CREATE TABLE IF NOT EXISTS share_transfer (
+    id BIGSERIAL PRIMARY KEY,
+    sender_id BIGINT NOT NULL REFERENCES share(user_id),
+    recipient_id BIGINT NOT NULL REFERENCES share(user_id),
+    amount REAL NOT NULL,
+    fee REAL NOT NULL,
+    -- id of the command that offered the transfer, so it can only be confirmed once
+    nonce BIGINT NOT NULL UNIQUE,
+    -- where the transfer was made, or NULL in DMs
+    guild_id BIGINT,
+    transferred_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS share_transfer_sender_id ON share_transfer(sender_id, transferred_at DESC);
+CREATE INDEX IF NOT EXISTS share_transfer_recipient_id ON share_transfer(recipient_id, transferred_at DESC);
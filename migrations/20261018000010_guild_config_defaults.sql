@@ -0,0 +1,4 @@
+-- the server the bot was written for, with the channels, role and user it used to have hard-coded
+INSERT INTO guild_config(guild_id, event_report_channel, event_ping_role, markov_channel, voice_alert_user)
+VALUES(464502358924197912, 924343631761006592, 816024905061367829, 464502359372857355, 392352456303968256)
+ON CONFLICT DO NOTHING;
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use crate::Context;
 
@@ -9,7 +9,7 @@ pub async fn _8ball(
     ctx: Context<'_>,
     #[description = "Your question"] question: Option<String>,
 ) -> Result<()> {
-    let random = thread_rng().gen_range(0..ANSWERS.len());
+    let random = ctx.data().rng.next().gen_range(0..ANSWERS.len());
     if let Some(question) = question {
         ctx.say(format!("```{question}```\n{}", ANSWERS[random]))
             .await?;
@@ -0,0 +1,390 @@
+use anyhow::{anyhow, Result};
+use poise::CreateReply;
+use serenity::all::{
+    ChannelId, Colour, CreateEmbed, GuildChannel, GuildId, Mentionable, Role, RoleId, User, UserId,
+};
+use sqlx::{query, query_as, PgPool};
+
+use crate::markov::sanitize::LinkMode;
+use crate::Context;
+
+/// Per-guild settings used by event handlers.
+#[derive(Debug, Default)]
+pub struct GuildConfig {
+    pub guild_id: i64,
+    pub event_report_channel: Option<i64>,
+    pub event_ping_role: Option<i64>,
+    pub markov_channel: Option<i64>,
+    pub voice_alert_user: Option<i64>,
+    pub markov_links: Option<String>,
+}
+
+impl GuildConfig {
+    /// Return `GuildConfig` for guild with `guild_id`, or an empty one if the guild has none.
+    pub async fn fetch(guild_id: GuildId, postgres: &PgPool) -> Result<Self> {
+        let guild_id: i64 = guild_id.get().try_into()?;
+
+        Ok(query_as!(
+            Self,
+            "SELECT * FROM guild_config WHERE guild_id = $1",
+            guild_id
+        )
+        .fetch_optional(postgres)
+        .await?
+        .unwrap_or(Self {
+            guild_id,
+            ..Self::default()
+        }))
+    }
+
+    /// Return every stored `GuildConfig`.
+    pub async fn fetch_all(postgres: &PgPool) -> Result<Vec<Self>> {
+        Ok(query_as!(Self, "SELECT * FROM guild_config")
+            .fetch_all(postgres)
+            .await?)
+    }
+
+    /// The channel scheduled event updates are reported in.
+    pub fn event_report_channel(&self) -> Option<ChannelId> {
+        to_id(self.event_report_channel).map(ChannelId::new)
+    }
+
+    /// The role pinged when a scheduled event starts.
+    pub fn event_ping_role(&self) -> Option<RoleId> {
+        to_id(self.event_ping_role).map(RoleId::new)
+    }
+
+    /// The channel the hourly Markov message is posted in.
+    pub fn markov_channel(&self) -> Option<ChannelId> {
+        to_id(self.markov_channel).map(ChannelId::new)
+    }
+
+    /// The user who is DMed when a voice channel fills up.
+    pub fn voice_alert_user(&self) -> Option<UserId> {
+        to_id(self.voice_alert_user).map(UserId::new)
+    }
+
+    /// What happens to links in Markov chain output.
+    pub fn markov_links(&self) -> LinkMode {
+        self.markov_links
+            .as_deref()
+            .and_then(LinkMode::parse)
+            .unwrap_or_default()
+    }
+
+    /// The `LinkMode` for guild with `guild_id`, or the default outside of guilds.
+    pub async fn fetch_markov_links(
+        guild_id: Option<GuildId>,
+        postgres: &PgPool,
+    ) -> Result<LinkMode> {
+        Ok(match guild_id {
+            Some(guild_id) => Self::fetch(guild_id, postgres).await?.markov_links(),
+            None => LinkMode::default(),
+        })
+    }
+
+    /// The channels whose messages the Markov chain learns from.
+    pub async fn markov_learn_channels(&self, postgres: &PgPool) -> Result<Vec<ChannelId>> {
+        Ok(query!(
+            "SELECT channel_id FROM markov_learn_channel WHERE guild_id = $1",
+            self.guild_id
+        )
+        .fetch_all(postgres)
+        .await?
+        .into_iter()
+        .filter_map(|row| to_id(Some(row.channel_id)).map(ChannelId::new))
+        .collect())
+    }
+
+    /// Whether the Markov chain learns from messages in channel with `channel_id`.
+    pub async fn is_markov_learn_channel(channel_id: ChannelId, postgres: &PgPool) -> Result<bool> {
+        let channel_id: i64 = channel_id.get().try_into()?;
+
+        Ok(query!(
+            r#"SELECT EXISTS(SELECT 1 FROM markov_learn_channel WHERE channel_id = $1) AS "exists!""#,
+            channel_id
+        )
+        .fetch_one(postgres)
+        .await?
+        .exists)
+    }
+}
+
+fn to_id(id: Option<i64>) -> Option<u64> {
+    id.and_then(|id| u64::try_from(id).ok())
+        .filter(|id| *id != 0)
+}
+
+fn from_id(id: Option<u64>) -> Result<Option<i64>> {
+    Ok(id.map(i64::try_from).transpose()?)
+}
+
+fn guild_id(ctx: &Context<'_>) -> Result<GuildId> {
+    ctx.guild_id()
+        .ok_or_else(|| anyhow!("config used outside of a guild"))
+}
+
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    required_permissions = "ADMINISTRATOR",
+    subcommands(
+        "view",
+        "event_channel",
+        "event_role",
+        "markov_channel",
+        "markov_learn",
+        "markov_links",
+        "voice_alert"
+    )
+)]
+pub async fn config(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// View this server's settings
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn view(ctx: Context<'_>) -> Result<()> {
+    let postgres = ctx.data().postgres.clone();
+    let config = GuildConfig::fetch(guild_id(&ctx)?, &postgres).await?;
+    let learn_channels = config.markov_learn_channels(&postgres).await?;
+
+    let unset = || "Not set".to_string();
+    ctx.send(
+        CreateReply::new().ephemeral(true).embed(
+            CreateEmbed::new()
+                .colour(Colour::from_rgb(231, 41, 57))
+                .title("Server Settings")
+                .field(
+                    "Event Report Channel",
+                    config
+                        .event_report_channel()
+                        .map_or_else(unset, |id| id.mention().to_string()),
+                    false,
+                )
+                .field(
+                    "Event Ping Role",
+                    config
+                        .event_ping_role()
+                        .map_or_else(unset, |id| id.mention().to_string()),
+                    false,
+                )
+                .field(
+                    "Markov Channel",
+                    config
+                        .markov_channel()
+                        .map_or_else(unset, |id| id.mention().to_string()),
+                    false,
+                )
+                .field(
+                    "Markov Learning Channels",
+                    if learn_channels.is_empty() {
+                        unset()
+                    } else {
+                        learn_channels
+                            .iter()
+                            .map(|id| id.mention().to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    },
+                    false,
+                )
+                .field("Markov Links", config.markov_links().as_str(), false)
+                .field(
+                    "Voice Alert User",
+                    config
+                        .voice_alert_user()
+                        .map_or_else(unset, |id| id.mention().to_string()),
+                    false,
+                ),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Set the channel scheduled events are reported in
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn event_channel(
+    ctx: Context<'_>,
+    #[description = "Channel to report in, leave empty to disable"]
+    #[channel_types("Text")]
+    channel: Option<GuildChannel>,
+) -> Result<()> {
+    let guild_id: i64 = guild_id(&ctx)?.get().try_into()?;
+    let channel_id = from_id(channel.map(|channel| channel.id.get()))?;
+
+    query!(
+        "INSERT INTO guild_config(guild_id, event_report_channel) VALUES($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET event_report_channel = EXCLUDED.event_report_channel",
+        guild_id,
+        channel_id
+    )
+    .execute(&ctx.data().postgres)
+    .await?;
+
+    ctx.send(
+        CreateReply::new()
+            .ephemeral(true)
+            .content("Event report channel updated."),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Set the role pinged when a scheduled event starts
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn event_role(
+    ctx: Context<'_>,
+    #[description = "Role to ping, leave empty to disable"] role: Option<Role>,
+) -> Result<()> {
+    let guild_id: i64 = guild_id(&ctx)?.get().try_into()?;
+    let role_id = from_id(role.map(|role| role.id.get()))?;
+
+    query!(
+        "INSERT INTO guild_config(guild_id, event_ping_role) VALUES($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET event_ping_role = EXCLUDED.event_ping_role",
+        guild_id,
+        role_id
+    )
+    .execute(&ctx.data().postgres)
+    .await?;
+
+    ctx.send(
+        CreateReply::new()
+            .ephemeral(true)
+            .content("Event ping role updated."),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Set the channel the bot talks in every hour
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn markov_channel(
+    ctx: Context<'_>,
+    #[description = "Channel to talk in, leave empty to disable"]
+    #[channel_types("Text")]
+    channel: Option<GuildChannel>,
+) -> Result<()> {
+    let guild_id: i64 = guild_id(&ctx)?.get().try_into()?;
+    let channel_id = from_id(channel.map(|channel| channel.id.get()))?;
+
+    query!(
+        "INSERT INTO guild_config(guild_id, markov_channel) VALUES($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET markov_channel = EXCLUDED.markov_channel",
+        guild_id,
+        channel_id
+    )
+    .execute(&ctx.data().postgres)
+    .await?;
+
+    ctx.send(
+        CreateReply::new()
+            .ephemeral(true)
+            .content("Markov channel updated."),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Choose whether the bot learns to talk from a channel's messages
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn markov_learn(
+    ctx: Context<'_>,
+    #[description = "Channel to learn from"]
+    #[channel_types("Text")]
+    channel: GuildChannel,
+    #[description = "Whether to learn from this channel"] enabled: bool,
+) -> Result<()> {
+    let guild_id: i64 = guild_id(&ctx)?.get().try_into()?;
+    let channel_id: i64 = channel.id.get().try_into()?;
+
+    if enabled {
+        query!(
+            "INSERT INTO markov_learn_channel(channel_id, guild_id) VALUES($1, $2)
+            ON CONFLICT (channel_id) DO NOTHING",
+            channel_id,
+            guild_id
+        )
+        .execute(&ctx.data().postgres)
+        .await?;
+    } else {
+        query!(
+            "DELETE FROM markov_learn_channel WHERE channel_id = $1",
+            channel_id
+        )
+        .execute(&ctx.data().postgres)
+        .await?;
+    }
+
+    ctx.send(CreateReply::new().ephemeral(true).content(format!(
+        "{} learning from {}.",
+        if enabled { "Now" } else { "No longer" },
+        channel.mention()
+    )))
+    .await?;
+
+    Ok(())
+}
+
+/// Choose what happens to links when the bot talks
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn markov_links(
+    ctx: Context<'_>,
+    #[description = "Keep links, strip them, or break them so they aren't clickable"]
+    mode: LinkMode,
+) -> Result<()> {
+    let guild_id: i64 = guild_id(&ctx)?.get().try_into()?;
+
+    query!(
+        "INSERT INTO guild_config(guild_id, markov_links) VALUES($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET markov_links = EXCLUDED.markov_links",
+        guild_id,
+        mode.as_str()
+    )
+    .execute(&ctx.data().postgres)
+    .await?;
+
+    ctx.send(
+        CreateReply::new()
+            .ephemeral(true)
+            .content("Markov link handling updated."),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Set the user who is messaged when a voice channel fills up
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn voice_alert(
+    ctx: Context<'_>,
+    #[description = "User to message, leave empty to disable"] user: Option<User>,
+) -> Result<()> {
+    let guild_id: i64 = guild_id(&ctx)?.get().try_into()?;
+    let user_id = from_id(user.map(|user| user.id.get()))?;
+
+    query!(
+        "INSERT INTO guild_config(guild_id, voice_alert_user) VALUES($1, $2)
+        ON CONFLICT (guild_id) DO UPDATE SET voice_alert_user = EXCLUDED.voice_alert_user",
+        guild_id,
+        user_id
+    )
+    .execute(&ctx.data().postgres)
+    .await?;
+
+    ctx.send(
+        CreateReply::new()
+            .ephemeral(true)
+            .content("Voice alert user updated."),
+    )
+    .await?;
+
+    Ok(())
+}
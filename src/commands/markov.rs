@@ -0,0 +1,263 @@
+use anyhow::Result;
+use poise::CreateReply;
+use serenity::all::{Colour, CreateAllowedMentions, CreateEmbed, Member, UserId};
+use sqlx::{query, PgPool};
+
+use crate::commands::config::GuildConfig;
+use crate::commands::truncate;
+use crate::markov::sanitize::sanitize;
+use crate::markov::store::ModelKey;
+use crate::markov::Bounds;
+use crate::Context;
+
+/// Whether user with `user_id` has agreed to have a model trained on their messages.
+pub async fn is_opted_in(user_id: UserId, postgres: &PgPool) -> Result<bool> {
+    let user_id: i64 = user_id.get().try_into()?;
+
+    Ok(query!(
+        r#"SELECT EXISTS(SELECT 1 FROM markov_user_opt_in WHERE user_id = $1) AS "exists!""#,
+        user_id
+    )
+    .fetch_one(postgres)
+    .await?
+    .exists)
+}
+
+#[poise::command(
+    slash_command,
+    subcommands("say", "imitate", "optin", "optout", "stats", "next")
+)]
+pub async fn markov(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Make the bot talk, optionally about something
+#[poise::command(slash_command)]
+pub async fn say(
+    ctx: Context<'_>,
+    #[description = "Word or words to start from"] seed: Option<String>,
+) -> Result<()> {
+    let markov = ctx
+        .data()
+        .markov
+        .for_guild(ctx.guild_id().map(|id| id.get()))
+        .await;
+    let markov = markov.read().await;
+
+    let mut rng = ctx.data().rng.next();
+    let generated_message = match &seed {
+        Some(seed) => {
+            markov
+                .generate_string_seeded(seed, Bounds::MESSAGE, &mut rng)
+                .await
+        }
+        None => markov.generate_string(Bounds::MESSAGE, &mut rng).await,
+    };
+    let links = GuildConfig::fetch_markov_links(ctx.guild_id(), &ctx.data().postgres).await?;
+    ctx.send(
+        CreateReply::new()
+            .content(sanitize(&generated_message, links))
+            .allowed_mentions(CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Talk like someone who has opted in
+#[poise::command(slash_command, guild_only)]
+pub async fn imitate(
+    ctx: Context<'_>,
+    #[description = "Who to imitate"] user: Member,
+) -> Result<()> {
+    let data = ctx.data();
+
+    if !is_opted_in(user.user.id, &data.postgres).await? {
+        ctx.send(CreateReply::new().ephemeral(true).content(format!(
+            "{} has not opted in to being imitated.",
+            user.display_name()
+        )))
+        .await?;
+        return Ok(());
+    }
+
+    let markov = data
+        .markov
+        .get(ModelKey::User {
+            guild_id: user.guild_id.get(),
+            user_id: user.user.id.get(),
+        })
+        .await;
+    let markov = markov.read().await;
+    if markov.is_empty() {
+        ctx.send(CreateReply::new().ephemeral(true).content(format!(
+            "I haven't learned anything from {} yet.",
+            user.display_name()
+        )))
+        .await?;
+        return Ok(());
+    }
+
+    let generated_message = markov
+        .generate_string(Bounds::MESSAGE, &mut data.rng.next())
+        .await;
+    let links = GuildConfig::fetch_markov_links(ctx.guild_id(), &data.postgres).await?;
+    ctx.send(
+        CreateReply::new()
+            .content(sanitize(&generated_message, links))
+            .allowed_mentions(CreateAllowedMentions::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Let the bot learn to talk like you
+#[poise::command(slash_command)]
+pub async fn optin(ctx: Context<'_>) -> Result<()> {
+    let user_id: i64 = ctx.author().id.get().try_into()?;
+
+    query!(
+        "INSERT INTO markov_user_opt_in(user_id) VALUES($1) ON CONFLICT DO NOTHING",
+        user_id
+    )
+    .execute(&ctx.data().postgres)
+    .await?;
+    ctx.data().markov.add_user(ctx.author().id.get()).await;
+
+    ctx.send(CreateReply::new().ephemeral(true).content(
+        "Opted in! I will learn from your messages in channels I'm allowed to learn from.",
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Stop the bot from learning to talk like you and delete your personal model
+#[poise::command(slash_command)]
+pub async fn optout(ctx: Context<'_>) -> Result<()> {
+    let author_id = ctx.author().id;
+    let user_id: i64 = author_id.get().try_into()?;
+
+    query!("DELETE FROM markov_user_opt_in WHERE user_id = $1", user_id)
+        .execute(&ctx.data().postgres)
+        .await?;
+    ctx.data().markov.remove_user(author_id.get()).await;
+
+    ctx.send(
+        CreateReply::new()
+            .ephemeral(true)
+            .content("Opted out. Your personal model has been deleted, but what you said stays in this server's model."),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Show numbers about the chain the bot talks with here
+#[poise::command(slash_command)]
+pub async fn stats(ctx: Context<'_>) -> Result<()> {
+    let markov = ctx
+        .data()
+        .markov
+        .for_guild(ctx.guild_id().map(|id| id.get()))
+        .await;
+    let stats = markov.read().await.stats();
+
+    ctx.send(
+        CreateReply::new().embed(
+            CreateEmbed::new()
+                .colour(Colour::from_rgb(231, 41, 57))
+                .title("Markov Stats")
+                .field("Words", stats.words.to_string(), true)
+                .field("Keys", stats.keys.to_string(), true)
+                .field("Transitions", stats.transitions.to_string(), true)
+                .field(
+                    "Unique Transitions",
+                    stats.unique_transitions.to_string(),
+                    true,
+                )
+                .field("Start Keys", stats.start_keys.to_string(), true)
+                .field("Key Size", stats.key_size.to_string(), true)
+                .field(
+                    "Size on Disk",
+                    stats
+                        .file_size
+                        .map_or_else(|| "Not saved".to_string(), size_format),
+                    true,
+                )
+                .field("Build/Load Time", format!("{:.2?}", stats.load_time), true),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// List the words that can follow a key
+#[poise::command(slash_command)]
+pub async fn next(
+    ctx: Context<'_>,
+    #[description = "Words to look up, as many as the chain's key size"] key: String,
+) -> Result<()> {
+    /// How many successors fit in a reply.
+    const MAX_SHOWN: usize = 25;
+    /// Most characters Discord allows in an embed title.
+    const MAX_TITLE_LEN: usize = 256;
+
+    let markov = ctx
+        .data()
+        .markov
+        .for_guild(ctx.guild_id().map(|id| id.get()))
+        .await;
+    let markov = markov.read().await;
+
+    let Some(successors) = markov.successors(&key) else {
+        ctx.send(CreateReply::new().ephemeral(true).content(format!(
+            "`{key}` is not in the chain. Keys are {} words long.",
+            markov.key_size()
+        )))
+        .await?;
+        return Ok(());
+    };
+
+    let total: usize = successors.iter().map(|(_, count)| count).sum();
+    let mut lines: Vec<String> = successors
+        .iter()
+        .take(MAX_SHOWN)
+        .map(|(word, count)| {
+            format!(
+                "{word} | {count} ({:.1}%)",
+                *count as f32 / total as f32 * 100.
+            )
+        })
+        .collect();
+    if successors.len() > MAX_SHOWN {
+        lines.push(format!("...and {} more", successors.len() - MAX_SHOWN));
+    }
+
+    ctx.send(
+        CreateReply::new().embed(
+            CreateEmbed::new()
+                .colour(Colour::from_rgb(231, 41, 57))
+                .title(truncate(&format!("After \"{key}\""), MAX_TITLE_LEN))
+                .description(format!("```\n{}\n```", lines.join("\n"))),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn size_format(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024. && unit < UNITS.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+
+    format!("{size:.2} {}", UNITS[unit])
+}
@@ -4,19 +4,23 @@ use poise::Command;
 use crate::DataWrapper;
 use _8ball::_8ball;
 use aon::aon;
+use config::config;
 use gelbooru::gelbooru;
+use markov::markov;
 use ping::ping;
 use register::register;
-use roll::roll;
+use roll::{check, roll};
 use shares::shares;
 
 #[path = "8ball.rs"]
 mod _8ball;
 mod aon;
+pub mod config;
 pub mod gelbooru;
+pub mod markov;
 mod ping;
 mod register;
-mod roll;
+pub mod roll;
 pub mod shares;
 
 pub fn commands() -> Vec<Command<DataWrapper, Error>> {
@@ -28,5 +32,18 @@ pub fn commands() -> Vec<Command<DataWrapper, Error>> {
         aon(),
         shares(),
         roll(),
+        check(),
+        config(),
+        markov(),
     ]
 }
+
+/// Shorten `text` to at most `max` characters, ending it with … if anything was cut.
+pub fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(max - 1).collect();
+    short.push('…');
+    short
+}
@@ -1,26 +1,122 @@
+use std::env;
 use std::fmt::{Display, Formatter};
 use std::iter::Peekable;
 use std::ops::Not;
-use std::str::Chars;
+use std::str::CharIndices;
 
-use anyhow::{ensure, Error, Result};
-use rand::{thread_rng, Rng};
+use anyhow::Result;
+use log::error;
+use rand::Rng;
 
+use crate::rng::RngSource;
 use crate::Context;
+pub use check::check;
+use history::history;
+use macros::{delete, list, run, save};
+use stats::stats;
 
-/// Roll dice
+mod check;
+mod history;
+mod macros;
+mod multi;
+mod stats;
+
+/// Longest message Discord will send.
+const MAX_MESSAGE_LEN: usize = 2000;
+
+/// Roll dice, and save, rerun and look back at rolls
+///
+/// Dice are rolled with `/roll dice roll:<expr>`. Before `/roll` had subcommands this was `/roll roll:<expr>`.
+#[poise::command(
+    slash_command,
+    subcommands("dice", "save", "list", "delete", "run", "stats", "history")
+)]
+pub async fn roll(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Roll dice, like `/roll roll:` used to
 #[poise::command(slash_command)]
-pub async fn roll(ctx: Context<'_>, #[description = "What to roll"] roll: String) -> Result<()> {
-    ctx.say(eval(shunt(&roll)?)?).await?;
+pub async fn dice(
+    ctx: Context<'_>,
+    #[description = "What to roll, like 2d6+3 fire; 1d8 slashing or 6x 4d6kh3, can use saved rolls as @name"]
+    roll: String,
+    #[description = "Seed from an earlier roll, to roll it again exactly"] seed: Option<u32>,
+) -> Result<()> {
+    roll_expression(ctx, &roll, seed).await
+}
+
+/// Roll every part of `input`, expanding any saved rolls in them, and reply with the outcomes.
+/// The rolls are made with `seed`, or a new seed if it's `None`.
+async fn roll_expression(ctx: Context<'_>, input: &str, seed: Option<u32>) -> Result<()> {
+    let data = ctx.data();
+    let limits = &data.roll_limits;
+    let seed = seed.unwrap_or_else(|| data.rng.seed());
+    let mut rng = RngSource::rng(seed);
+
+    let parts = multi::parse(input, limits)?;
+    let mut outcomes = Vec::new();
+    for part in &parts {
+        let expanded = expand_macros(ctx, part.expression).await?;
+        let tokens = shunt(&expanded, limits).map_err(|error| error.in_expression(&expanded))?;
+        for _ in 0..part.repeat {
+            outcomes.push(
+                eval(tokens.clone(), limits, &mut rng)
+                    .map_err(|error| error.in_expression(&expanded))?,
+            );
+        }
+    }
+    let total = multi::grand_total(&outcomes)?;
+
+    let footer = format!("\n-# Seed {seed}");
+    let mut reply = multi::reply(&parts, &outcomes, &total, false) + &footer;
+    if reply.chars().count() > MAX_MESSAGE_LEN {
+        reply = multi::reply(&parts, &outcomes, &total, true) + &footer;
+    }
+    if reply.chars().count() > MAX_MESSAGE_LEN {
+        reply = format!("Total: **{total}**{footer}");
+    }
+    ctx.say(reply).await?;
+
+    let dice: Vec<(i32, i32)> = outcomes
+        .iter()
+        .flat_map(|outcome| outcome.dice.iter().copied())
+        .collect();
+    // the roll has already been shown, so failing to save it shouldn't look like the roll failed
+    if let Err(err) = history::record(ctx, input, seed, &total, &dice).await {
+        error!("couldn't save roll: {err}");
+    }
 
     Ok(())
 }
 
+/// `expression` with the author's saved rolls filled in.
+async fn expand_macros(ctx: Context<'_>, expression: &str) -> Result<String> {
+    let data = ctx.data();
+
+    Ok(match expression.contains('@') {
+        true => {
+            let macros = macros::fetch_all(ctx.author().id, &data.postgres).await?;
+            macros::expand(expression, &macros, &data.roll_limits)?
+        }
+        false => expression.to_string(),
+    })
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Operator {
     Dice,
+    Explode,
+    Compound,
     KeepHighest,
     KeepLowest,
+    DropHighest,
+    DropLowest,
+    Reroll,
+    RerollOnce,
+    Successes,
+    Failures,
+    Neg,
     Add,
     Sub,
     Mul,
@@ -32,60 +128,334 @@ pub enum Operator {
 impl Operator {
     fn precedence(&self) -> u8 {
         match self {
-            Operator::Dice => 4,
-            Operator::KeepHighest | Operator::KeepLowest => 3,
+            Operator::Dice => 6,
+            Operator::Explode | Operator::Compound => 5,
+            Operator::KeepHighest
+            | Operator::KeepLowest
+            | Operator::DropHighest
+            | Operator::DropLowest
+            | Operator::Reroll
+            | Operator::RerollOnce
+            | Operator::Successes
+            | Operator::Failures => 4,
+            Operator::Neg => 3,
             Operator::Mul | Operator::Div => 2,
             Operator::Add | Operator::Sub => 1,
             _ => 0,
         }
     }
+
+    /// Whether the operator only takes the operand before it.
+    fn is_postfix(&self) -> bool {
+        matches!(self, Operator::Explode | Operator::Compound)
+    }
+
+    /// Whether the operator changes the roll before it rather than turning it into a number.
+    fn modifies_roll(&self) -> bool {
+        matches!(
+            self,
+            Operator::Explode
+                | Operator::Compound
+                | Operator::KeepHighest
+                | Operator::KeepLowest
+                | Operator::DropHighest
+                | Operator::DropLowest
+                | Operator::Reroll
+                | Operator::RerollOnce
+                | Operator::Successes
+                | Operator::Failures
+        )
+    }
+
+    /// How many operands the operator takes.
+    fn operands(&self) -> usize {
+        match self.is_postfix() || *self == Operator::Neg {
+            true => 1,
+            false => 2,
+        }
+    }
+
+    /// How the operator is written in a breakdown.
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Dice => "d",
+            Operator::Explode => "!",
+            Operator::Compound => "!!",
+            Operator::KeepHighest => "kh",
+            Operator::KeepLowest => "kl",
+            Operator::DropHighest => "dh",
+            Operator::DropLowest => "dl",
+            Operator::Reroll => "r",
+            Operator::RerollOnce => "ro",
+            Operator::Successes => ">=",
+            Operator::Failures => "f<=",
+            Operator::Neg => "-",
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "\\*",
+            Operator::Div => "/",
+            Operator::LParen => "(",
+            Operator::RParen => ")",
+        }
+    }
+}
+
+/// Most extra dice a single die can explode into, so a lucky streak can't go on forever.
+const MAX_EXPLOSIONS: usize = 100;
+
+/// A single die in a `Roll`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Die {
+    value: i32,
+    /// Whether the die was dropped or rerolled and no longer counts towards the total.
+    dropped: bool,
+    /// Whether the die rolled its highest side and exploded.
+    exploded: bool,
+}
+
+impl Die {
+    fn new(value: i32) -> Self {
+        Self {
+            value,
+            dropped: false,
+            exploded: false,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Roll {
     total: i32,
-    rolls: Vec<i32>,
+    dice: Vec<Die>,
+    sides: i32,
+    /// Dice at or above this count as a success, making the total a count of successes.
+    success: Option<i32>,
+    /// Dice at or below this count as a failure and take a success away.
+    failure: Option<i32>,
 }
 
 impl Roll {
     /// Roll m n-sided dice and create a Roll with the results
-    fn mdn(m: i32, n: i32) -> Result<Self> {
-        ensure!(m > 0, n > 0);
+    fn mdn(m: i32, n: i32, limits: &RollLimits, rng: &mut impl Rng) -> RollResult<Self> {
+        ensure(m > 0 && n > 0, RollErrorKind::InvalidDice)?;
+        ensure(
+            m as usize <= limits.max_dice,
+            RollErrorKind::TooManyDice(limits.max_dice),
+        )?;
+        ensure(
+            n <= limits.max_sides,
+            RollErrorKind::TooManySides(limits.max_sides),
+        )?;
 
-        let mut total: i32 = 0;
-        let mut rolls = Vec::new();
+        let dice = (0..m).map(|_| Die::new(rng.gen_range(1..=n))).collect();
 
-        let mut rng = thread_rng();
+        let mut roll = Self {
+            total: 0,
+            dice,
+            sides: n,
+            success: None,
+            failure: None,
+        };
+        roll.recount()?;
+        Ok(roll)
+    }
 
-        for _ in 0..m {
-            let roll = rng.gen_range(1..=n);
-            total += roll;
-            rolls.push(roll);
+    /// The sides and first roll of every die, including dropped ones.
+    fn rolled(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        // compounded dice only started out on their highest side
+        self.dice
+            .iter()
+            .map(|die| (self.sides, die.value.min(self.sides)))
+    }
+
+    /// Work out the total again after dice have changed.
+    fn recount(&mut self) -> RollResult<()> {
+        let mut kept = self.dice.iter().filter(|die| !die.dropped);
+
+        self.total = match self.success {
+            Some(success) => kept
+                .map(|die| match die.value {
+                    value if value >= success => 1,
+                    value if self.failure.is_some_and(|failure| value <= failure) => -1,
+                    _ => 0,
+                })
+                .sum(),
+            None => kept
+                .try_fold(0i32, |total, die| total.checked_add(die.value))
+                .ok_or(RollErrorKind::Overflow)?,
+        };
+        Ok(())
+    }
+
+    fn keep_highest(self, n: usize) -> RollResult<Token> {
+        self.keep(n, true)
+    }
+
+    fn keep_lowest(self, n: usize) -> RollResult<Token> {
+        self.keep(n, false)
+    }
+
+    /// Drop all but the `n` highest or lowest dice that haven't been dropped yet.
+    /// Dice stay in the order they were rolled.
+    fn keep(mut self, n: usize, highest: bool) -> RollResult<Token> {
+        let mut kept: Vec<usize> = (0..self.dice.len())
+            .filter(|i| !self.dice[*i].dropped)
+            .collect();
+        kept.sort_by_key(|i| self.dice[*i].value);
+        if highest {
+            kept.reverse();
+        }
+
+        for i in kept.into_iter().skip(n) {
+            self.dice[i].dropped = true;
         }
+        self.recount()?;
+        Ok(Token::DiceRoll(self))
+    }
 
-        Ok(Self { total, rolls })
+    /// Drop the `n` highest dice that haven't been dropped yet.
+    fn drop_highest(self, n: usize) -> RollResult<Token> {
+        let kept = self.dice.iter().filter(|die| !die.dropped).count();
+        self.keep(kept.saturating_sub(n), false)
     }
 
-    fn keep_highest(&mut self, n: usize) -> Token {
-        self.rolls
-            .sort_unstable_by(|a, b| b.partial_cmp(a).unwrap());
-        while self.rolls.len() > n {
-            if let Some(roll) = self.rolls.pop() {
-                self.total -= roll;
+    /// Drop the `n` lowest dice that haven't been dropped yet.
+    fn drop_lowest(self, n: usize) -> RollResult<Token> {
+        let kept = self.dice.iter().filter(|die| !die.dropped).count();
+        self.keep(kept.saturating_sub(n), true)
+    }
+
+    /// Roll another die for every die showing its highest side, and again for those.
+    fn explode(mut self, limits: &RollLimits, rng: &mut impl Rng) -> RollResult<Token> {
+        ensure(self.sides > 1, RollErrorKind::EndlessExplosion(self.sides))?;
+
+        let mut dice = Vec::with_capacity(self.dice.len());
+        for die in self.dice {
+            let mut exploding = !die.dropped && die.value == self.sides;
+            dice.push(die);
+            for _ in 0..MAX_EXPLOSIONS {
+                if !exploding {
+                    break;
+                }
+                dice.last_mut().unwrap().exploded = true;
+                let value = rng.gen_range(1..=self.sides);
+                dice.push(Die::new(value));
+                exploding = value == self.sides;
             }
+            ensure(
+                dice.len() <= limits.max_dice,
+                RollErrorKind::TooManyDice(limits.max_dice),
+            )?;
         }
-        Token::Int(self.total)
+
+        self.dice = dice;
+        self.recount()?;
+        Ok(Token::DiceRoll(self))
     }
 
-    fn keep_lowest(&mut self, n: usize) -> Token {
-        self.rolls
-            .sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
-        while self.rolls.len() > n {
-            if let Some(roll) = self.rolls.pop() {
-                self.total -= roll;
+    /// Like `explode`, but extra rolls are added to the die that exploded.
+    fn compound(mut self, rng: &mut impl Rng) -> RollResult<Token> {
+        ensure(self.sides > 1, RollErrorKind::EndlessExplosion(self.sides))?;
+
+        for die in self.dice.iter_mut().filter(|die| !die.dropped) {
+            let mut last = die.value;
+            for _ in 0..MAX_EXPLOSIONS {
+                if last != self.sides {
+                    break;
+                }
+                die.exploded = true;
+                last = rng.gen_range(1..=self.sides);
+                die.value = die.value.checked_add(last).ok_or(RollErrorKind::Overflow)?;
             }
         }
-        Token::Int(self.total)
+
+        self.recount()?;
+        Ok(Token::DiceRoll(self))
+    }
+
+    /// Reroll dice showing `n` or lower until they're higher, or only once if `once`.
+    /// Rerolled dice are kept but dropped.
+    fn reroll(
+        mut self,
+        n: i32,
+        once: bool,
+        limits: &RollLimits,
+        rng: &mut impl Rng,
+    ) -> RollResult<Token> {
+        ensure(
+            once || n < self.sides,
+            RollErrorKind::EndlessReroll {
+                sides: self.sides,
+                below: n,
+            },
+        )?;
+
+        let mut dice = Vec::with_capacity(self.dice.len());
+        for mut die in self.dice {
+            if die.dropped || die.value > n {
+                dice.push(die);
+                continue;
+            }
+            loop {
+                die.dropped = true;
+                dice.push(die);
+                die = Die::new(rng.gen_range(1..=self.sides));
+                if once || die.value > n {
+                    break;
+                }
+            }
+            dice.push(die);
+            ensure(
+                dice.len() <= limits.max_dice,
+                RollErrorKind::TooManyDice(limits.max_dice),
+            )?;
+        }
+
+        self.dice = dice;
+        self.recount()?;
+        Ok(Token::DiceRoll(self))
+    }
+
+    /// Count dice showing `n` or higher as successes instead of adding them up.
+    fn successes(mut self, n: i32) -> RollResult<Token> {
+        self.success = Some(n);
+        self.recount()?;
+        Ok(Token::DiceRoll(self))
+    }
+
+    /// Take a success away for every die showing `n` or lower.
+    fn failures(mut self, n: i32) -> RollResult<Token> {
+        ensure(
+            self.success.is_some(),
+            RollErrorKind::FailuresWithoutSuccesses,
+        )?;
+
+        self.failure = Some(n);
+        self.recount()?;
+        Ok(Token::DiceRoll(self))
+    }
+}
+
+impl Display for Roll {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let dice = self
+            .dice
+            .iter()
+            .map(|die| {
+                let shown = format!("{}{}", die.value, if die.exploded { "!" } else { "" });
+                if die.dropped {
+                    format!("~~{shown}~~")
+                } else if self.success.is_some_and(|success| die.value >= success) {
+                    format!("**{shown}**")
+                } else if self.failure.is_some_and(|failure| die.value <= failure) {
+                    format!("_{shown}_")
+                } else {
+                    shown
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "[{dice}]")
     }
 }
 
@@ -105,90 +475,79 @@ impl Token {
         }
     }
 
-    fn int(self) -> Result<i32> {
+    fn int(self) -> RollResult<i32> {
         match self {
             Self::Int(n) => Ok(n),
             Self::DiceRoll(roll) => Ok(roll.total),
-            _ => Err(Error::msg(format!("{:?}: not an integer", self))),
+            _ => Err(RollErrorKind::NotAnInteger.into()),
         }
     }
 
-    fn roll(self) -> Result<Roll> {
+    fn float(self) -> RollResult<f32> {
         match self {
-            Self::DiceRoll(roll) => Ok(roll),
-            _ => Err(Error::msg(format!("{:?}: not a roll", self))),
+            Self::Float(n) => Ok(n),
+            token => Ok(token.int()? as f32),
         }
     }
 
-    fn add(self, rhs: Token) -> Result<Token> {
-        match self {
-            Token::Int(_) | Token::DiceRoll(_) => match rhs {
-                Token::Int(_) | Token::DiceRoll(_) => Ok(Token::Int(self.int()? + rhs.int()?)),
-                Token::Float(rhs) => Ok(Token::Float(self.int()? as f32 + rhs)),
-                _ => Err(Error::msg(format!("{:?}: not an operand", self))),
-            },
-            Token::Float(lhs) => match rhs {
-                Token::Int(_) | Token::DiceRoll(_) => Ok(Token::Float(lhs + rhs.int()? as f32)),
-                Token::Float(rhs) => Ok(Token::Float(lhs + rhs)),
-                _ => Err(Error::msg(format!("{:?}: not an operand", self))),
-            },
-            _ => Err(Error::msg(format!("{:?}: not an operand", self))),
-        }
+    /// How many dice to keep or drop.
+    fn count(self) -> RollResult<usize> {
+        let n = self.int()?;
+        usize::try_from(n).map_err(|_| RollError::from(RollErrorKind::InvalidCount(n)))
     }
 
-    fn sub(self, rhs: Token) -> Result<Token> {
+    fn roll(self, operator: &Operator) -> RollResult<Roll> {
         match self {
-            Token::Int(_) | Token::DiceRoll(_) => match rhs {
-                Token::Int(_) | Token::DiceRoll(_) => Ok(Token::Int(self.int()? - rhs.int()?)),
-                Token::Float(rhs) => Ok(Token::Float(self.int()? as f32 - rhs)),
-                _ => Err(Error::msg(format!("{:?}: not an operand", self))),
-            },
-            Token::Float(lhs) => match rhs {
-                Token::Int(_) | Token::DiceRoll(_) => Ok(Token::Float(lhs - rhs.int()? as f32)),
-                Token::Float(rhs) => Ok(Token::Float(lhs - rhs)),
-                _ => Err(Error::msg(format!("{:?}: not an operand", self))),
-            },
-            _ => Err(Error::msg(format!("{:?}: not an operand", self))),
+            Self::DiceRoll(roll) => Ok(roll),
+            _ => Err(RollErrorKind::NotARoll(operator.symbol()).into()),
         }
     }
 
-    fn mul(self, rhs: Token) -> Result<Token> {
-        match self {
-            Token::Int(_) | Token::DiceRoll(_) => match rhs {
-                Token::Int(_) | Token::DiceRoll(_) => Ok(Token::Int(self.int()? * rhs.int()?)),
-                Token::Float(rhs) => Ok(Token::Float(self.int()? as f32 * rhs)),
-                _ => Err(Error::msg(format!("{:?}: not an operand", self))),
-            },
-            Token::Float(lhs) => match rhs {
-                Token::Int(_) | Token::DiceRoll(_) => Ok(Token::Float(lhs * rhs.int()? as f32)),
-                Token::Float(rhs) => Ok(Token::Float(lhs * rhs)),
-                _ => Err(Error::msg(format!("{:?}: not an operand", self))),
-            },
-            _ => Err(Error::msg(format!("{:?}: not an operand", self))),
-        }
+    fn add(self, rhs: Token) -> RollResult<Token> {
+        self.arithmetic(rhs, i32::checked_add, |lhs, rhs| lhs + rhs)
     }
 
-    fn div(self, rhs: Token) -> Result<Token> {
-        match self {
-            Token::Int(_) | Token::DiceRoll(_) => match rhs {
-                Token::Int(_) | Token::DiceRoll(_) => {
-                    Ok(Token::Float(self.int()? as f32 / rhs.int()? as f32))
-                }
-                Token::Float(rhs) => Ok(Token::Float(self.int()? as f32 / rhs)),
-                _ => Err(Error::msg(format!("{:?}: not an operand", self))),
-            },
-            Token::Float(lhs) => match rhs {
-                Token::Int(_) | Token::DiceRoll(_) => Ok(Token::Float(lhs / rhs.int()? as f32)),
-                Token::Float(rhs) => Ok(Token::Float(lhs / rhs)),
-                _ => Err(Error::msg(format!("{:?}: not an operand", self))),
-            },
-            _ => Err(Error::msg(format!("{:?}: not an operand", self))),
+    fn sub(self, rhs: Token) -> RollResult<Token> {
+        self.arithmetic(rhs, i32::checked_sub, |lhs, rhs| lhs - rhs)
+    }
+
+    fn mul(self, rhs: Token) -> RollResult<Token> {
+        self.arithmetic(rhs, i32::checked_mul, |lhs, rhs| lhs * rhs)
+    }
+
+    fn div(self, rhs: Token) -> RollResult<Token> {
+        let (lhs, rhs) = (self.float()?, rhs.float()?);
+        ensure(rhs != 0., RollErrorKind::DivisionByZero)?;
+
+        finite(lhs / rhs)
+    }
+
+    /// Apply `int` if both sides are integers, or `float` if either is a float.
+    fn arithmetic(
+        self,
+        rhs: Token,
+        int: fn(i32, i32) -> Option<i32>,
+        float: fn(f32, f32) -> f32,
+    ) -> RollResult<Token> {
+        match (self, rhs) {
+            (lhs @ Token::Float(_), rhs) | (lhs, rhs @ Token::Float(_)) => {
+                finite(float(lhs.float()?, rhs.float()?))
+            }
+            (lhs, rhs) => Ok(Token::Int(
+                int(lhs.int()?, rhs.int()?).ok_or(RollErrorKind::Overflow)?,
+            )),
         }
     }
 }
 
+/// A float result, unless it overflowed.
+fn finite(n: f32) -> RollResult<Token> {
+    ensure(n.is_finite(), RollErrorKind::Overflow)?;
+    Ok(Token::Float(n))
+}
+
 impl TryFrom<char> for Token {
-    type Error = Error;
+    type Error = RollError;
 
     fn try_from(value: char) -> std::result::Result<Self, Self::Error> {
         match value {
@@ -199,19 +558,27 @@ impl TryFrom<char> for Token {
             '-' => Ok(Token::Op(Operator::Sub)),
             '(' => Ok(Token::Op(Operator::LParen)),
             ')' => Ok(Token::Op(Operator::RParen)),
-            _ => Err(Error::msg(format!("unknown token: {value}"))),
+            _ => Err(RollErrorKind::UnknownToken(value.to_string()).into()),
         }
     }
 }
 
 impl TryFrom<&str> for Token {
-    type Error = Error;
+    type Error = RollError;
 
     fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
         match value {
             "kh" => Ok(Token::Op(Operator::KeepHighest)),
             "kl" => Ok(Token::Op(Operator::KeepLowest)),
-            _ => Err(Error::msg(format!("unknown token: {value}"))),
+            "dh" => Ok(Token::Op(Operator::DropHighest)),
+            "dl" => Ok(Token::Op(Operator::DropLowest)),
+            "r" => Ok(Token::Op(Operator::Reroll)),
+            "ro" => Ok(Token::Op(Operator::RerollOnce)),
+            ">=" => Ok(Token::Op(Operator::Successes)),
+            "f<=" => Ok(Token::Op(Operator::Failures)),
+            "!" => Ok(Token::Op(Operator::Explode)),
+            "!!" => Ok(Token::Op(Operator::Compound)),
+            _ => Err(RollErrorKind::UnknownToken(value.to_string()).into()),
         }
     }
 }
@@ -227,184 +594,915 @@ impl Display for Token {
     }
 }
 
+/// Where in the expression each character is, by byte offset.
+type Chars<'a> = Peekable<CharIndices<'a>>;
+
 // this dijkstra guy was a real one
-pub fn shunt(input: &str) -> Result<Vec<Token>> {
-    let mut out: Vec<Token> = Vec::new();
-    let mut ops: Vec<Token> = Vec::new();
+pub fn shunt(input: &str, limits: &RollLimits) -> RollResult<Vec<(Token, usize)>> {
+    ensure(
+        input.chars().count() <= limits.max_length,
+        RollErrorKind::TooLong(limits.max_length),
+    )?;
 
-    let mut iter = input.chars().peekable();
-    while let Some(token) = iter.next() {
+    let mut out: Vec<(Token, usize)> = Vec::new();
+    let mut ops: Vec<(Token, usize)> = Vec::new();
+    // whether the next token has to be a number, a roll or something in parentheses
+    let mut expect_operand = true;
+
+    let mut iter = input.char_indices().peekable();
+    while let Some((position, token)) = iter.next() {
         match token {
-            '0'..='9' => out.push(num(token, &mut iter)?),
-            'd' | 'k' | '*' | '/' | '+' | '-' => {
-                let token: Token = if token == 'k' {
-                    let mut keep = token.to_string();
-                    keep.push(
-                        iter.next()
-                            .ok_or_else(|| Error::msg("k must be followed by h or l"))?,
-                    );
-                    keep.as_str().try_into()?
-                } else {
-                    token.try_into()?
+            '0'..='9' | '.' => {
+                ensure(expect_operand, RollErrorKind::ExpectedOperator).at(position)?;
+                out.push((num(token, &mut iter).at(position)?, position));
+                expect_operand = false;
+            }
+            '-' if expect_operand => {
+                // nothing binds to the right of a prefix operator yet, so there's nothing to pop
+                ops.push((Token::Op(Operator::Neg), position));
+            }
+            'd' | 'k' | 'r' | '>' | 'f' | '!' | '*' | '/' | '+' | '-' => {
+                let token = operator(token, &mut iter).at(position)?;
+                let Token::Op(operator) = &token else {
+                    unreachable!("operator() only returns operators");
                 };
-                while ops.last().is_some_and(|token_2| {
+
+                if expect_operand && *operator == Operator::Dice {
+                    // `d20` is short for `1d20`
+                    out.push((Token::Int(1), position));
+                } else {
+                    ensure(
+                        !expect_operand,
+                        RollErrorKind::MissingOperand(operator.symbol()),
+                    )
+                    .at(position)?;
+                }
+
+                while ops.last().is_some_and(|(token_2, _)| {
                     token_2 != &Token::Op(Operator::LParen)
                         && token_2.precedence() >= token.precedence()
                 }) {
                     let op2 = ops.pop().unwrap();
                     out.push(op2);
                 }
-                ops.push(token);
+                // postfix operators already have their operand, so they go straight to the output
+                if operator.is_postfix() {
+                    out.push((token, position));
+                } else {
+                    ops.push((token, position));
+                    expect_operand = true;
+                }
+            }
+            '(' => {
+                ensure(expect_operand, RollErrorKind::ExpectedOperator).at(position)?;
+                ops.push((token.try_into()?, position));
             }
-            '(' => ops.push(token.try_into()?),
             ')' => {
+                ensure(!expect_operand, RollErrorKind::MissingOperand(")")).at(position)?;
                 while ops
                     .last()
-                    .is_some_and(|op| op != &Token::Op(Operator::LParen))
+                    .is_some_and(|(op, _)| op != &Token::Op(Operator::LParen))
                 {
                     out.push(ops.pop().unwrap());
                 }
-                if ops
-                    .last()
-                    .is_some_and(|op| op == &Token::Op(Operator::LParen))
-                {
-                    ops.pop();
-                }
+                ensure(ops.pop().is_some(), RollErrorKind::UnmatchedParen(')')).at(position)?;
             }
             ' ' => continue,
-            _ => return Err(Error::msg("invalid expression")),
+            _ => {
+                return Err(
+                    RollError::from(RollErrorKind::UnknownToken(token.to_string())).at(position),
+                )
+            }
         }
     }
+    ensure(
+        !expect_operand,
+        match ops.last() {
+            Some((Token::Op(operator), _)) => RollErrorKind::MissingOperand(operator.symbol()),
+            _ => RollErrorKind::InvalidExpression,
+        },
+    )
+    .at(input.len())?;
 
-    while let Some(op) = ops.pop() {
-        out.push(op);
+    while let Some((op, position)) = ops.pop() {
+        ensure(
+            op != Token::Op(Operator::LParen),
+            RollErrorKind::UnmatchedParen('('),
+        )
+        .at(position)?;
+        out.push((op, position));
     }
 
     Ok(out)
 }
 
-fn num(first: char, iter: &mut Peekable<Chars>) -> Result<Token> {
-    let mut saw_dot: bool = false;
+/// Read the operator starting with `first`, including the rest of it if it's more than one character.
+fn operator(first: char, iter: &mut Chars) -> RollResult<Token> {
+    let mut next_if_eq = |expected: char| iter.next_if(|(_, char)| *char == expected).is_some();
+
+    match first {
+        'k' if next_if_eq('h') => "kh".try_into(),
+        'k' if next_if_eq('l') => "kl".try_into(),
+        'd' if next_if_eq('h') => "dh".try_into(),
+        'd' if next_if_eq('l') => "dl".try_into(),
+        'r' if next_if_eq('o') => "ro".try_into(),
+        '!' if next_if_eq('!') => "!!".try_into(),
+        '>' if next_if_eq('=') => ">=".try_into(),
+        'f' if next_if_eq('<') && next_if_eq('=') => "f<=".try_into(),
+        'k' | '>' | 'f' => Err(RollErrorKind::UnknownToken(first.to_string()).into()),
+        'r' | '!' => first.to_string().as_str().try_into(),
+        _ => first.try_into(),
+    }
+}
+
+fn num(first: char, iter: &mut Chars) -> RollResult<Token> {
+    let mut saw_dot: bool = first == '.';
     let mut num = first.to_string();
 
-    while iter
-        .peek()
-        .is_some_and(|char| char.is_ascii_digit() || saw_dot.not() && *char == '.')
+    while let Some((_, char)) =
+        iter.next_if(|(_, char)| char.is_ascii_digit() || saw_dot.not() && *char == '.')
     {
-        let char = iter.next().unwrap();
-        if saw_dot.not() && char == '.' {
+        if char == '.' {
             saw_dot = true;
         }
         num.push(char);
     }
 
+    let invalid = || RollError::from(RollErrorKind::InvalidNumber(num.clone()));
     match saw_dot {
-        true => Ok(Token::Float(num.parse()?)),
-        false => Ok(Token::Int(num.parse()?)),
+        true => Ok(Token::Float(num.parse().map_err(|_| invalid())?)),
+        false => Ok(Token::Int(num.parse().map_err(|_| invalid())?)),
     }
 }
 
-pub fn eval(input: Vec<Token>) -> Result<String> {
-    let mut stack: Vec<Token> = Vec::new();
+/// The result of evaluating a roll.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub total: Token,
+    /// The expression with every roll replaced by its dice, e.g. `[~~4~~, 17, 12] + 5`.
+    pub breakdown: String,
+    /// The sides and first roll of every die rolled, including dropped ones.
+    pub dice: Vec<(i32, i32)>,
+}
+
+/// An operand on the evaluation stack, along with how it's shown in the breakdown.
+struct Term {
+    token: Token,
+    shown: String,
+    /// Precedence of the operator that produced `shown`, to know when it needs parentheses.
+    precedence: u8,
+}
 
-    for token in input {
-        match token {
+impl Term {
+    fn new(token: Token) -> Self {
+        let shown = match &token {
+            Token::DiceRoll(roll) => roll.to_string(),
+            token => token.to_string(),
+        };
+        Self {
+            token,
+            shown,
+            precedence: u8::MAX,
+        }
+    }
+
+    /// `shown`, parenthesised if it binds looser than an operator with `precedence`.
+    fn shown_in(&self, precedence: u8, strict: bool) -> String {
+        if self.precedence < precedence || strict && self.precedence == precedence {
+            format!("({})", self.shown)
+        } else {
+            self.shown.clone()
+        }
+    }
+}
+
+pub fn eval(
+    input: Vec<(Token, usize)>,
+    limits: &RollLimits,
+    rng: &mut impl Rng,
+) -> RollResult<Outcome> {
+    let mut stack: Vec<Term> = Vec::new();
+    let mut dice = Vec::new();
+
+    for (token, position) in input {
+        let term = match token {
             Token::Op(operator) => {
-                let (rhs, mut lhs) = (stack.pop(), stack.pop());
-                if lhs.is_none() && rhs.as_ref().is_some_and(|_| operator == Operator::Dice) {
-                    lhs = Some(Token::Int(1));
-                } else if lhs.is_none() && rhs.is_none() {
-                    return Err(Error::msg(format!(
-                        "not enough operands for operator: {:?}",
-                        operator
-                    )));
+                // a roll being modified lives on in the result, so its dice are only taken once it's used up
+                let used_up = operator.operands() - usize::from(operator.modifies_roll());
+                for term in stack.iter().rev().take(used_up) {
+                    if let Token::DiceRoll(roll) = &term.token {
+                        dice.extend(roll.rolled());
+                    }
                 }
-                let (lhs, rhs) = (lhs.unwrap(), rhs.unwrap());
-                stack.push(match operator {
-                    Operator::Dice => Token::DiceRoll(Roll::mdn(lhs.int()?, rhs.int()?)?),
-                    Operator::KeepHighest => lhs.roll()?.keep_highest(rhs.int()?.try_into()?),
-                    Operator::KeepLowest => lhs.roll()?.keep_lowest(rhs.int()?.try_into()?),
-                    Operator::Add => lhs.add(rhs)?,
-                    Operator::Sub => lhs.sub(rhs)?,
-                    Operator::Mul => lhs.mul(rhs)?,
-                    Operator::Div => lhs.div(rhs)?,
-                    _ => return Err(Error::msg(format!("{:?}: not an operator", operator))),
-                });
+                apply(operator, &mut stack, limits, rng).at(position)?
             }
-            _ => stack.push(token),
+            _ => Term::new(token),
+        };
+        stack.push(term);
+    }
+
+    ensure(stack.len() == 1, RollErrorKind::InvalidExpression)?;
+    let term = stack.pop().unwrap();
+    if let Token::DiceRoll(roll) = &term.token {
+        dice.extend(roll.rolled());
+    }
+    Ok(Outcome {
+        total: term.token,
+        breakdown: term.shown,
+        dice,
+    })
+}
+
+/// Apply `operator` to the terms it takes from the top of `stack`.
+fn apply(
+    operator: Operator,
+    stack: &mut Vec<Term>,
+    limits: &RollLimits,
+    rng: &mut impl Rng,
+) -> RollResult<Term> {
+    let missing = || RollError::from(RollErrorKind::MissingOperand(operator.symbol()));
+    let precedence = operator.precedence();
+
+    if operator.is_postfix() {
+        let roll = stack.pop().ok_or_else(missing)?.token.roll(&operator)?;
+        return Ok(Term::new(match operator {
+            Operator::Explode => roll.explode(limits, rng)?,
+            Operator::Compound => roll.compound(rng)?,
+            _ => return Err(RollErrorKind::InvalidExpression.into()),
+        }));
+    }
+
+    if operator == Operator::Neg {
+        let operand = stack.pop().ok_or_else(missing)?;
+        let shown = format!("-{}", operand.shown_in(precedence, false));
+        let token = match operand.token {
+            Token::Float(n) => Token::Float(-n),
+            token => Token::Int(token.int()?.checked_neg().ok_or(RollErrorKind::Overflow)?),
+        };
+        return Ok(Term {
+            token,
+            shown,
+            precedence,
+        });
+    }
+
+    let (Some(rhs), Some(lhs)) = (stack.pop(), stack.pop()) else {
+        return Err(missing());
+    };
+    let shown = format!(
+        "{} {} {}",
+        lhs.shown_in(precedence, false),
+        operator.symbol(),
+        rhs.shown_in(
+            precedence,
+            matches!(operator, Operator::Sub | Operator::Div)
+        )
+    );
+    let token = match operator {
+        Operator::Dice => {
+            Token::DiceRoll(Roll::mdn(lhs.token.int()?, rhs.token.int()?, limits, rng)?)
+        }
+        Operator::KeepHighest => lhs
+            .token
+            .roll(&operator)?
+            .keep_highest(rhs.token.count()?)?,
+        Operator::KeepLowest => lhs.token.roll(&operator)?.keep_lowest(rhs.token.count()?)?,
+        Operator::DropHighest => lhs
+            .token
+            .roll(&operator)?
+            .drop_highest(rhs.token.count()?)?,
+        Operator::DropLowest => lhs.token.roll(&operator)?.drop_lowest(rhs.token.count()?)?,
+        Operator::Reroll => {
+            lhs.token
+                .roll(&operator)?
+                .reroll(rhs.token.int()?, false, limits, rng)?
+        }
+        Operator::RerollOnce => {
+            lhs.token
+                .roll(&operator)?
+                .reroll(rhs.token.int()?, true, limits, rng)?
+        }
+        Operator::Successes => lhs.token.roll(&operator)?.successes(rhs.token.int()?)?,
+        Operator::Failures => lhs.token.roll(&operator)?.failures(rhs.token.int()?)?,
+        Operator::Add => lhs.token.add(rhs.token)?,
+        Operator::Sub => lhs.token.sub(rhs.token)?,
+        Operator::Mul => lhs.token.mul(rhs.token)?,
+        Operator::Div => lhs.token.div(rhs.token)?,
+        _ => return Err(RollErrorKind::InvalidExpression.into()),
+    };
+
+    Ok(match token {
+        // rolls show their dice rather than how they were made
+        Token::DiceRoll(_) => Term::new(token),
+        _ => Term {
+            token,
+            shown,
+            precedence,
+        },
+    })
+}
+
+/// Limits on what can be rolled, so a single roll can't exhaust memory.
+#[derive(Clone, Debug)]
+pub struct RollLimits {
+    /// Most dice in a single roll, including ones added by exploding and rerolling.
+    pub max_dice: usize,
+    pub max_sides: i32,
+    /// Most characters in an expression.
+    pub max_length: usize,
+    /// Most rolls one command can make, counting every repeat.
+    pub max_rolls: usize,
+}
+
+impl Default for RollLimits {
+    fn default() -> Self {
+        Self {
+            max_dice: 1000,
+            max_sides: 1_000_000,
+            max_length: 256,
+            max_rolls: 20,
         }
     }
+}
+
+impl RollLimits {
+    /// Read limits from `ROLL_MAX_DICE`, `ROLL_MAX_SIDES`, `ROLL_MAX_LENGTH` and `ROLL_MAX_ROLLS`,
+    /// using the defaults for any that aren't set.
+    pub fn from_env() -> Result<Self> {
+        let default = Self::default();
+
+        Ok(Self {
+            max_dice: env_or("ROLL_MAX_DICE", default.max_dice)?,
+            max_sides: env_or("ROLL_MAX_SIDES", default.max_sides)?,
+            max_length: env_or("ROLL_MAX_LENGTH", default.max_length)?,
+            max_rolls: env_or("ROLL_MAX_ROLLS", default.max_rolls)?,
+        })
+    }
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match env::var(key) {
+        Ok(value) => Ok(value.parse()?),
+        Err(_) => Ok(default),
+    }
+}
+
+type RollResult<T> = std::result::Result<T, RollError>;
+
+/// An error in a roll, and where in the expression it happened.
+#[derive(Debug)]
+pub struct RollError {
+    pub kind: RollErrorKind,
+    /// Byte offset of the token that caused the error.
+    position: Option<usize>,
+    /// The expression, to point out `position` in.
+    expression: Option<String>,
+}
+
+impl RollError {
+    /// Attach the expression the error happened in.
+    pub fn in_expression(mut self, expression: &str) -> Self {
+        self.expression = Some(expression.to_string());
+        self
+    }
+
+    /// Set where the error happened, unless something more specific already did.
+    fn at(mut self, position: usize) -> Self {
+        self.position.get_or_insert(position);
+        self
+    }
+}
+
+impl From<RollErrorKind> for RollError {
+    fn from(kind: RollErrorKind) -> Self {
+        Self {
+            kind,
+            position: None,
+            expression: None,
+        }
+    }
+}
+
+/// Pinning errors to the token that caused them.
+trait At {
+    fn at(self, position: usize) -> Self;
+}
+
+impl<T> At for RollResult<T> {
+    fn at(self, position: usize) -> Self {
+        self.map_err(|error| error.at(position))
+    }
+}
+
+/// Fail with `kind` unless `condition` holds.
+fn ensure(condition: bool, kind: RollErrorKind) -> RollResult<()> {
+    match condition {
+        true => Ok(()),
+        false => Err(kind.into()),
+    }
+}
+
+impl Display for RollError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+
+        if let (Some(position), Some(expression)) = (self.position, &self.expression) {
+            if expression.trim().is_empty() {
+                return Ok(());
+            }
+            let column = expression
+                .get(..position)
+                .map_or(0, |before| before.chars().count());
+            write!(f, "\n```\n{expression}\n{}^\n```", " ".repeat(column))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RollError {}
+
+#[derive(Debug)]
+pub enum RollErrorKind {
+    TooLong(usize),
+    TooManyRolls(usize),
+    TooManyDice(usize),
+    TooManySides(i32),
+    InvalidDice,
+    InvalidCount(i32),
+    InvalidNumber(String),
+    UnknownToken(String),
+    MissingOperand(&'static str),
+    UnknownMacro(String),
+    RecursiveMacro(String),
+    InvalidMacroName(usize),
+    ExpectedOperator,
+    UnmatchedParen(char),
+    NotAnInteger,
+    NotARoll(&'static str),
+    EndlessExplosion(i32),
+    EndlessReroll { sides: i32, below: i32 },
+    FailuresWithoutSuccesses,
+    DivisionByZero,
+    Overflow,
+    InvalidExpression,
+}
 
-    ensure!(stack.len() == 1);
-    Ok(stack[0].to_string())
+impl Display for RollErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLong(max) => write!(f, "Rolls can be at most {max} characters long."),
+            Self::TooManyRolls(max) => write!(f, "At most {max} rolls can be made at once."),
+            Self::TooManyDice(max) => write!(f, "Rolls can have at most {max} dice."),
+            Self::TooManySides(max) => write!(f, "Dice can have at most {max} sides."),
+            Self::InvalidDice => write!(f, "Dice need a positive number of dice and sides."),
+            Self::InvalidCount(n) => write!(f, "Can't keep or drop {n} dice."),
+            Self::InvalidNumber(n) => write!(f, "{n} is not a valid number."),
+            Self::UnknownToken(token) => write!(f, "Unknown operator: {token}"),
+            Self::MissingOperand(op) => write!(f, "{op} is missing a number or roll."),
+            Self::UnknownMacro(name) => write!(f, "You don't have a saved roll called `@{name}`."),
+            Self::RecursiveMacro(name) => write!(f, "`@{name}` ends up using itself."),
+            Self::InvalidMacroName(max) => write!(
+                f,
+                "Names can only have letters, numbers and underscores, and be at most {max} characters long."
+            ),
+            Self::ExpectedOperator => write!(f, "Expected an operator."),
+            Self::UnmatchedParen(paren) => write!(f, "Unmatched {paren}"),
+            Self::NotAnInteger => write!(f, "Expected a whole number."),
+            Self::NotARoll(op) => write!(f, "{op} can only be used on dice."),
+            Self::EndlessExplosion(sides) => write!(f, "d{sides} dice would explode forever."),
+            Self::EndlessReroll { sides, below } => write!(
+                f,
+                "Rerolling d{sides} dice on {below} or lower would never stop."
+            ),
+            Self::FailuresWithoutSuccesses => write!(f, "f<= needs a >= before it."),
+            Self::DivisionByZero => write!(f, "Can't divide by zero."),
+            Self::Overflow => write!(f, "The result is too large."),
+            Self::InvalidExpression => write!(f, "That's not a valid roll."),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
 
-    use super::{eval, shunt};
+    use super::{eval, shunt, Outcome, RngSource, RollErrorKind, RollLimits, RollResult, Token};
+
+    fn run(input: &str) -> RollResult<Outcome> {
+        run_seeded(input, 0)
+    }
+
+    fn run_seeded(input: &str, seed: u32) -> RollResult<Outcome> {
+        let limits = RollLimits::default();
+        eval(shunt(input, &limits)?, &limits, &mut RngSource::rng(seed))
+    }
+
+    /// Roll `input` with the fixed seed and check its total and breakdown.
+    fn assert_roll(input: &str, total: &str, breakdown: &str) -> Result<()> {
+        let outcome = run(input)?;
+        assert_eq!(outcome.total.to_string(), total, "total of {input}");
+        assert_eq!(outcome.breakdown, breakdown, "breakdown of {input}");
+        Ok(())
+    }
 
     #[test]
     fn implied_one() -> Result<()> {
-        assert!((1..=20).contains(&eval(shunt("d20")?)?.parse::<u32>()?));
+        assert_roll("d20", "14", "[14]")?;
         Ok(())
     }
 
     #[test]
     fn keep_highest() -> Result<()> {
-        assert!((2..=40).contains(&eval(shunt("3d20kh2")?)?.parse::<u32>()?));
+        assert_roll("3d20kh2", "30", "[~~14~~, 15, 15]")?;
         Ok(())
     }
 
     #[test]
     fn keep_lowest() -> Result<()> {
-        assert!((3..=60).contains(&eval(shunt("4d20kl3")?)?.parse::<u32>()?));
+        assert_roll("4d20kl3", "39", "[14, 15, ~~15~~, 10]")?;
         Ok(())
     }
 
     #[test]
     fn addition() -> Result<()> {
-        assert!((6..=25).contains(&eval(shunt("d20 + 5")?)?.parse::<u32>()?));
+        assert_roll("d20 + 5", "19", "[14] + 5")?;
         Ok(())
     }
 
     #[test]
     fn subtraction() -> Result<()> {
-        assert!((-4..=15).contains(&eval(shunt("d20 - 5")?)?.parse::<i32>()?));
+        assert_roll("d20 - 5", "9", "[14] - 5")?;
         Ok(())
     }
 
     #[test]
     fn multiplication() -> Result<()> {
-        assert!((5..=100).contains(&eval(shunt("d20 * 5")?)?.parse::<u32>()?));
+        assert_roll("d20 * 5", "70", r"[14] \* 5")?;
         Ok(())
     }
 
     #[test]
     fn division() -> Result<()> {
-        assert!((0.2..=4.).contains(&eval(shunt("d20 / 5")?)?.parse::<f32>()?));
+        assert_roll("d20 / 5", "2.8", "[14] / 5")?;
         Ok(())
     }
 
     #[test]
     fn precedence() -> Result<()> {
-        assert!(
-            (7.2..=26.2).contains(&eval(shunt("1 + 2 * 3 - 4 / 5 + 2d20kh1")?)?.parse::<f32>()?)
-        );
+        assert_roll(
+            "1 + 2 * 3 - 4 / 5 + 2d20kh1",
+            "21.2",
+            r"1 + 2 \* 3 - 4 / 5 + [~~14~~, 15]",
+        )?;
         Ok(())
     }
 
     #[test]
     fn parens() -> Result<()> {
-        assert!((0.4..=20.4)
-            .contains(&eval(shunt("(1 + 2) * (3 - 4) / 5 + 2d20kh1")?)?.parse::<f32>()?));
+        assert_roll(
+            "(1 + 2) * (3 - 4) / 5 + 2d20kh1",
+            "14.4",
+            r"(1 + 2) \* (3 - 4) / 5 + [~~14~~, 15]",
+        )?;
         Ok(())
     }
 
     #[test]
     fn computed_dice() -> Result<()> {
-        assert!((3..=60).contains(&eval(shunt("(1 + 2)d((12 - 2) * 2)")?)?.parse::<u32>()?));
+        assert_roll("(1 + 2)d((12 - 2) * 2)", "44", "[14, 15, 15]")?;
+        Ok(())
+    }
+
+    #[test]
+    fn dropped_dice_are_struck() -> Result<()> {
+        assert_roll("4d20kh3 + 1", "45", "[14, 15, 15, ~~10~~] + 1")?;
+        let outcome = run("4d20kh3 + 1")?;
+        let Token::Int(total) = outcome.total else {
+            panic!("{:?}: not an integer", outcome.total);
+        };
+
+        let (dice, rest) = outcome.breakdown.split_once(']').unwrap();
+        let dice: Vec<&str> = dice.trim_start_matches('[').split(", ").collect();
+        assert_eq!(dice.len(), 4);
+        assert_eq!(dice.iter().filter(|die| die.starts_with("~~")).count(), 1);
+        let kept: i32 = dice
+            .iter()
+            .filter(|die| !die.starts_with("~~"))
+            .map(|die| die.parse::<i32>())
+            .sum::<std::result::Result<i32, _>>()?;
+        assert_eq!(rest, " + 1");
+        assert_eq!(total, kept + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn breakdown_parens() -> Result<()> {
+        assert_eq!(
+            run("(1 + 2) * 3 - (4 - 5)")?.breakdown,
+            "(1 + 2) \\* 3 - (4 - 5)"
+        );
+        Ok(())
+    }
+
+    /// The dice shown in the first roll of `breakdown`.
+    fn dice(breakdown: &str) -> Vec<&str> {
+        let (dice, _) = breakdown.trim_start_matches('[').split_once(']').unwrap();
+        dice.split(", ").collect()
+    }
+
+    /// The value of a die as shown in a breakdown, without formatting.
+    fn value(die: &str) -> i32 {
+        die.trim_matches(['~', '*', '_', '!']).parse().unwrap()
+    }
+
+    #[test]
+    fn explode() -> Result<()> {
+        assert_roll(
+            "20d6!",
+            "99",
+            "[5, 5, 4, 5, 4, 1, 2, 6!, 5, 4, 4, 3, 6!, 4, 6!, 1, 6!, 2, 6!, 3, 5, 4, 4, 3, 1]",
+        )?;
+        let outcome = run("20d6!")?;
+        let dice = dice(&outcome.breakdown);
+
+        let exploded = dice.iter().filter(|die| die.ends_with('!')).count();
+        assert_eq!(dice.len(), 20 + exploded);
+        for die in &dice {
+            assert_eq!(die.ends_with('!'), value(die) == 6);
+        }
+        assert_eq!(
+            outcome.total.int()?,
+            dice.iter().map(|die| value(die)).sum::<i32>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compound() -> Result<()> {
+        assert_roll(
+            "20d6!!",
+            "99",
+            "[5, 5, 4, 5, 4, 1, 2, 11!, 4, 4, 3, 10!, 7!, 8!, 9!, 5, 4, 4, 3, 1]",
+        )?;
+        let outcome = run("20d6!!")?;
+        let dice = dice(&outcome.breakdown);
+
+        assert_eq!(dice.len(), 20);
+        for die in &dice {
+            assert_eq!(die.ends_with('!'), value(die) >= 6);
+            assert_ne!(value(die) % 6, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reroll() -> Result<()> {
+        assert_roll("20d6r2", "92", "[5, 5, 4, 5, 4, ~~1~~, 5, ~~2~~, 4, 6, 4, 4, 3, 6, 6, 6, 6, 5, 4, 4, 3, ~~1~~, ~~1~~, ~~2~~, 3]")?;
+        let outcome = run("20d6r2")?;
+        let dice = dice(&outcome.breakdown);
+
+        for die in &dice {
+            assert_eq!(die.starts_with("~~"), value(die) <= 2);
+        }
+        assert_eq!(dice.iter().filter(|die| !die.starts_with("~~")).count(), 20);
+        Ok(())
+    }
+
+    #[test]
+    fn reroll_once() -> Result<()> {
+        assert_roll(
+            "20d6ro2",
+            "90",
+            "[5, 5, 4, 5, 4, ~~1~~, 5, ~~2~~, 4, 6, 4, 4, 3, 6, 6, 6, 6, 5, 4, 4, 3, ~~1~~, 1]",
+        )?;
+        let outcome = run("20d6ro2")?;
+        let dice = dice(&outcome.breakdown);
+
+        let rerolled = dice.iter().filter(|die| die.starts_with("~~")).count();
+        assert_eq!(dice.len(), 20 + rerolled);
+        assert!(dice
+            .iter()
+            .filter(|die| die.starts_with("~~"))
+            .all(|die| value(die) <= 2));
+        Ok(())
+    }
+
+    #[test]
+    fn endless_reroll() -> Result<()> {
+        assert!(run("d6r6").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn drop_highest() -> Result<()> {
+        assert_roll("4d20dh1", "39", "[14, 15, ~~15~~, 10]")?;
+        Ok(())
+    }
+
+    #[test]
+    fn drop_lowest() -> Result<()> {
+        assert_roll("4d6dl1", "15", "[5, 5, ~~4~~, 5]")?;
+        let outcome = run("4d6dl1")?;
+        let dice = dice(&outcome.breakdown);
+
+        let dropped: Vec<i32> = dice
+            .iter()
+            .filter(|die| die.starts_with("~~"))
+            .map(|die| value(die))
+            .collect();
+        assert_eq!(dropped.len(), 1);
+        assert!(dice.iter().all(|die| value(die) >= dropped[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn successes() -> Result<()> {
+        assert_roll(
+            "10d10>=8",
+            "4",
+            "[7, **8**, **8**, 6, 6, 1, 6, 5, **9**, **9**]",
+        )?;
+        let outcome = run("10d10>=8")?;
+        let dice = dice(&outcome.breakdown);
+
+        let successes = dice.iter().filter(|die| value(die) >= 8).count() as i32;
+        assert_eq!(outcome.total.int()?, successes);
+        Ok(())
+    }
+
+    #[test]
+    fn failures() -> Result<()> {
+        assert_roll(
+            "10d10>=8f<=1",
+            "3",
+            "[7, **8**, **8**, 6, 6, _1_, 6, 5, **9**, **9**]",
+        )?;
+        let outcome = run("10d10>=8f<=1")?;
+        let dice = dice(&outcome.breakdown);
+
+        let successes = dice.iter().filter(|die| value(die) >= 8).count() as i32;
+        let failures = dice.iter().filter(|die| value(die) <= 1).count() as i32;
+        assert_eq!(outcome.total.int()?, successes - failures);
+        Ok(())
+    }
+
+    #[test]
+    fn failures_need_successes() -> Result<()> {
+        assert!(run("10d10f<=1").is_err());
+        Ok(())
+    }
+
+    fn roll_error(input: &str) -> Option<RollErrorKind> {
+        run(input).err().map(|error| error.kind)
+    }
+
+    #[test]
+    fn limits() {
+        assert!(matches!(
+            roll_error("1001d6"),
+            Some(RollErrorKind::TooManyDice(_))
+        ));
+        assert!(matches!(
+            roll_error("d1000001"),
+            Some(RollErrorKind::TooManySides(_))
+        ));
+        assert!(matches!(
+            roll_error("999d2!"),
+            Some(RollErrorKind::TooManyDice(_))
+        ));
+        assert!(matches!(
+            roll_error(&"1+".repeat(200)),
+            Some(RollErrorKind::TooLong(_))
+        ));
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(matches!(
+            roll_error("2147483647 + 1"),
+            Some(RollErrorKind::Overflow)
+        ));
+        assert!(matches!(
+            roll_error("99999 * 99999"),
+            Some(RollErrorKind::Overflow)
+        ));
+        assert!(matches!(
+            roll_error("0 - 2147483647 - 2"),
+            Some(RollErrorKind::Overflow)
+        ));
+        assert!(matches!(
+            roll_error("99999999999"),
+            Some(RollErrorKind::InvalidNumber(_))
+        ));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert!(matches!(
+            roll_error("d20 / 0"),
+            Some(RollErrorKind::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn missing_operand() {
+        assert!(matches!(
+            roll_error("+5"),
+            Some(RollErrorKind::MissingOperand(_))
+        ));
+        assert!(matches!(
+            roll_error("5kh1"),
+            Some(RollErrorKind::NotARoll(_))
+        ));
+    }
+
+    #[test]
+    fn unary_minus() -> Result<()> {
+        assert_roll("-1d4", "-3", "-[3]")?;
+        assert_eq!(run("2*-3")?.total.int()?, -6);
+        assert_eq!(run("-(2 + 3)")?.total.int()?, -5);
+        assert_eq!(run("--5")?.total.int()?, 5);
+        assert_eq!(run("-2 * 3")?.breakdown, "-2 \\* 3");
+        assert_roll("-3d6kh2", "-10", "-[5, 5, ~~4~~]")?;
+        Ok(())
+    }
+
+    #[test]
+    fn implied_one_after_operator() -> Result<()> {
+        assert_roll("2 * d20", "28", r"2 \* [14]")?;
+        Ok(())
+    }
+
+    #[test]
+    fn unbalanced_parens() {
+        assert!(matches!(
+            roll_error("(1 + 2"),
+            Some(RollErrorKind::UnmatchedParen('('))
+        ));
+        assert!(matches!(
+            roll_error("1 + 2)"),
+            Some(RollErrorKind::UnmatchedParen(')'))
+        ));
+        assert!(matches!(
+            roll_error("2 (3)"),
+            Some(RollErrorKind::ExpectedOperator)
+        ));
+        assert!(matches!(
+            roll_error("1 +"),
+            Some(RollErrorKind::MissingOperand("+"))
+        ));
+    }
+
+    #[test]
+    fn error_position() {
+        let limits = RollLimits::default();
+        let error = shunt("3d6 + x", &limits)
+            .unwrap_err()
+            .in_expression("3d6 + x");
+        assert_eq!(
+            error.to_string(),
+            "Unknown operator: x\n```\n3d6 + x\n      ^\n```"
+        );
+
+        let error = run("4d6 + 2kh1").unwrap_err().in_expression("4d6 + 2kh1");
+        assert!(error.to_string().ends_with("4d6 + 2kh1\n       ^\n```"));
+    }
+
+    #[test]
+    fn replay() -> Result<()> {
+        let outcome = run_seeded("10d20!kh5 + d6", 42)?;
+        let replayed = run_seeded("10d20!kh5 + d6", 42)?;
+        assert_eq!(outcome.breakdown, replayed.breakdown);
+        assert_eq!(outcome.total, replayed.total);
+        assert_ne!(
+            outcome.breakdown,
+            run_seeded("10d20!kh5 + d6", 43)?.breakdown
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rolled_dice() -> Result<()> {
+        assert_roll(
+            "4d6kh3 + (1d4)d8!! - 2d10r1",
+            "15",
+            "[5, 5, ~~4~~, 5] + [1, 12!, 5] - [9, 9]",
+        )?;
+        let outcome = run("4d6kh3 + (1d4)d8!! - 2d10r1")?;
+        let count = |sides| outcome.dice.iter().filter(|die| die.0 == sides).count();
+
+        assert!(outcome
+            .dice
+            .iter()
+            .all(|(sides, value)| (1..=*sides).contains(value)));
+        assert_eq!(count(6), 4);
+        assert_eq!(count(4), 1);
+        let d4 = outcome.dice.iter().find(|die| die.0 == 4).unwrap().1;
+        assert_eq!(count(8), d4 as usize);
+        assert_eq!(count(10), 2);
         Ok(())
     }
 }
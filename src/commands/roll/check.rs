@@ -0,0 +1,236 @@
+use std::fmt::{Display, Formatter};
+
+use anyhow::Result;
+use log::error;
+use rand::Rng;
+
+use super::{eval, history, shunt, Outcome, RollLimits, RollResult};
+use crate::rng::RngSource;
+use crate::Context;
+
+/// How many d20s a check rolls, and which one counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum Mode {
+    #[default]
+    #[name = "Normal"]
+    Normal,
+    /// Roll twice and keep the higher roll.
+    #[name = "Fortune"]
+    Fortune,
+    /// Roll twice and keep the lower roll.
+    #[name = "Misfortune"]
+    Misfortune,
+}
+
+impl Mode {
+    fn dice(self) -> &'static str {
+        match self {
+            Self::Normal => "d20",
+            Self::Fortune => "2d20kh1",
+            Self::Misfortune => "2d20kl1",
+        }
+    }
+}
+
+/// How well a check went, worst first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Degree {
+    CriticalFailure,
+    Failure,
+    Success,
+    CriticalSuccess,
+}
+
+impl Degree {
+    /// The degree of success a check with `total` gets against `dc`, before the natural roll is taken into account.
+    fn of(total: i32, dc: i32) -> Self {
+        if total >= dc + 10 {
+            Self::CriticalSuccess
+        } else if total >= dc {
+            Self::Success
+        } else if total <= dc - 10 {
+            Self::CriticalFailure
+        } else {
+            Self::Failure
+        }
+    }
+
+    /// One degree better on a natural 20, or one worse on a natural 1.
+    fn adjusted(self, natural: i32) -> Self {
+        match (natural, self) {
+            (20, Self::CriticalFailure) => Self::Failure,
+            (20, Self::Failure) => Self::Success,
+            (20, _) => Self::CriticalSuccess,
+            (1, Self::CriticalSuccess) => Self::Success,
+            (1, Self::Success) => Self::Failure,
+            (1, _) => Self::CriticalFailure,
+            _ => self,
+        }
+    }
+}
+
+impl Display for Degree {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CriticalFailure => write!(f, "Critical Failure"),
+            Self::Failure => write!(f, "Failure"),
+            Self::Success => write!(f, "Success"),
+            Self::CriticalSuccess => write!(f, "Critical Success"),
+        }
+    }
+}
+
+/// A rolled check and how well it went.
+pub struct Check {
+    pub expression: String,
+    pub outcome: Outcome,
+    pub natural: i32,
+    pub total: i32,
+    /// The degree of success from the total alone.
+    pub unadjusted: Degree,
+    pub degree: Degree,
+}
+
+impl Check {
+    /// Roll a check with `modifier` against `dc`.
+    pub fn roll(
+        modifier: i32,
+        dc: i32,
+        mode: Mode,
+        limits: &RollLimits,
+        rng: &mut impl Rng,
+    ) -> RollResult<Self> {
+        let expression = match modifier {
+            modifier if modifier < 0 => format!("{} - {}", mode.dice(), -modifier),
+            modifier => format!("{} + {modifier}", mode.dice()),
+        };
+        let outcome = shunt(&expression, limits)
+            .and_then(|tokens| eval(tokens, limits, rng))
+            .map_err(|error| error.in_expression(&expression))?;
+
+        let total = outcome.total.clone().int()?;
+        let natural = total - modifier;
+        let unadjusted = Degree::of(total, dc);
+        Ok(Self {
+            expression,
+            outcome,
+            natural,
+            total,
+            unadjusted,
+            degree: unadjusted.adjusted(natural),
+        })
+    }
+}
+
+/// Roll a Pathfinder check against a DC
+#[poise::command(slash_command)]
+pub async fn check(
+    ctx: Context<'_>,
+    #[description = "Modifier added to the d20"]
+    #[min = -100]
+    #[max = 100]
+    modifier: i32,
+    #[description = "DC to meet or beat"]
+    #[min = 0]
+    #[max = 100]
+    dc: i32,
+    #[description = "Roll twice and keep the higher (fortune) or lower (misfortune) roll"]
+    mode: Option<Mode>,
+    #[description = "Seed from an earlier check, to roll it again exactly"] seed: Option<u32>,
+) -> Result<()> {
+    let data = ctx.data();
+    let seed = seed.unwrap_or_else(|| data.rng.seed());
+    let check = Check::roll(
+        modifier,
+        dc,
+        mode.unwrap_or_default(),
+        &data.roll_limits,
+        &mut RngSource::rng(seed),
+    )?;
+
+    let mut result = format!("Natural {}: **{}**", check.natural, check.degree);
+    if check.degree != check.unadjusted {
+        let direction = match check.degree > check.unadjusted {
+            true => "up",
+            false => "down",
+        };
+        result += &format!(" ({direction} from {})", check.unadjusted);
+    }
+    let reply = format!(
+        "`{}` vs DC {dc}: {} = **{}**\n{result}\n-# Seed {seed}",
+        check.expression, check.outcome.breakdown, check.total
+    );
+    ctx.say(reply).await?;
+
+    // the check has already been shown, so failing to save it shouldn't look like the check failed
+    if let Err(err) = history::record(
+        ctx,
+        &check.expression,
+        seed,
+        &check.outcome.total,
+        &check.outcome.dice,
+    )
+    .await
+    {
+        error!("couldn't save check: {err}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Check, Degree, Mode, RollLimits};
+    use crate::rng::RngSource;
+
+    #[test]
+    fn degrees() {
+        assert_eq!(Degree::of(35, 25), Degree::CriticalSuccess);
+        assert_eq!(Degree::of(25, 25), Degree::Success);
+        assert_eq!(Degree::of(24, 25), Degree::Failure);
+        assert_eq!(Degree::of(15, 25), Degree::CriticalFailure);
+    }
+
+    #[test]
+    fn natural_adjustments() {
+        assert_eq!(Degree::Failure.adjusted(20), Degree::Success);
+        assert_eq!(Degree::CriticalFailure.adjusted(20), Degree::Failure);
+        assert_eq!(
+            Degree::CriticalSuccess.adjusted(20),
+            Degree::CriticalSuccess
+        );
+        assert_eq!(Degree::Success.adjusted(1), Degree::Failure);
+        assert_eq!(Degree::CriticalFailure.adjusted(1), Degree::CriticalFailure);
+        assert_eq!(Degree::Success.adjusted(19), Degree::Success);
+    }
+
+    #[test]
+    fn fortune_and_misfortune() {
+        let limits = RollLimits::default();
+        let rolls =
+            |check: &Check| -> Vec<i32> { check.outcome.dice.iter().map(|die| die.1).collect() };
+
+        for seed in 0..50 {
+            let roll =
+                |mode| Check::roll(-3, 15, mode, &limits, &mut RngSource::rng(seed)).unwrap();
+            let normal = roll(Mode::Normal);
+            let fortune = roll(Mode::Fortune);
+            let misfortune = roll(Mode::Misfortune);
+
+            assert_eq!(rolls(&normal), [normal.natural]);
+            assert_eq!(rolls(&fortune).len(), 2);
+            assert_eq!(Some(fortune.natural), rolls(&fortune).into_iter().max());
+            assert_eq!(
+                Some(misfortune.natural),
+                rolls(&misfortune).into_iter().min()
+            );
+            for check in [normal, fortune, misfortune] {
+                assert_eq!(check.total, check.natural - 3);
+                assert_eq!(
+                    check.degree,
+                    Degree::of(check.total, 15).adjusted(check.natural)
+                );
+            }
+        }
+    }
+}
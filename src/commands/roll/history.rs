@@ -0,0 +1,170 @@
+use anyhow::Result;
+use poise::CreateReply;
+use serenity::all::{Colour, CreateEmbed, User};
+use sqlx::query;
+
+use super::Token;
+use crate::commands::truncate;
+use crate::Context;
+
+/// How many rolls `/roll history` shows if not told otherwise.
+const DEFAULT_LIMIT: i64 = 10;
+/// How many die sizes luck is shown for.
+const LUCK_SIZES: i64 = 5;
+/// Most characters of an expression shown in `/roll history`.
+const MAX_EXPRESSION_LEN: usize = 100;
+/// Most characters Discord allows in an embed description.
+const MAX_DESCRIPTION_LEN: usize = 4096;
+
+/// Save a roll by the author of `ctx` to their history.
+pub async fn record(
+    ctx: Context<'_>,
+    expression: &str,
+    seed: u32,
+    total: &Token,
+    dice: &[(i32, i32)],
+) -> Result<()> {
+    let user_id: i64 = ctx.author().id.get().try_into()?;
+    let guild_id = ctx
+        .guild_id()
+        .map(|id| i64::try_from(id.get()))
+        .transpose()?;
+    let channel_id: i64 = ctx.channel_id().get().try_into()?;
+    let (sides, values): (Vec<i32>, Vec<i32>) = dice.iter().copied().unzip();
+
+    query!(
+        "INSERT INTO roll_history(user_id, guild_id, channel_id, expression, seed, total, die_sides, die_values)
+        VALUES($1, $2, $3, $4, $5, $6, $7, $8)",
+        user_id,
+        guild_id,
+        channel_id,
+        expression,
+        i64::from(seed),
+        f64::from(total.clone().float()?),
+        &sides,
+        &values
+    )
+    .execute(&ctx.data().postgres)
+    .await?;
+
+    Ok(())
+}
+
+/// Show recent rolls and how lucky they've been
+#[poise::command(slash_command)]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "Whose rolls to show, defaults to you"] user: Option<User>,
+    #[description = "How many rolls to show"]
+    #[min = 1]
+    #[max = 25]
+    limit: Option<i64>,
+) -> Result<()> {
+    let postgres = &ctx.data().postgres;
+    let user = user.as_ref().unwrap_or_else(|| ctx.author());
+    let user_id: i64 = user.id.get().try_into()?;
+    let guild_id = ctx
+        .guild_id()
+        .map(|id| i64::try_from(id.get()))
+        .transpose()?;
+
+    let rolls = query!(
+        "SELECT expression, seed, total, rolled_at FROM roll_history
+        WHERE user_id = $1 AND guild_id IS NOT DISTINCT FROM $2
+        ORDER BY rolled_at DESC LIMIT $3",
+        user_id,
+        guild_id,
+        limit.unwrap_or(DEFAULT_LIMIT)
+    )
+    .fetch_all(postgres)
+    .await?;
+
+    if rolls.is_empty() {
+        ctx.send(
+            CreateReply::new()
+                .ephemeral(true)
+                .content(format!("{} hasn't rolled anything here yet.", user.name)),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    // luck is over every roll, not just the ones shown
+    let luck = query!(
+        r#"SELECT die.sides AS "sides!", COUNT(*) AS "count!", AVG(die.value)::DOUBLE PRECISION AS "average!"
+        FROM roll_history, unnest(die_sides, die_values) AS die(sides, value)
+        WHERE user_id = $1 AND guild_id IS NOT DISTINCT FROM $2
+        GROUP BY die.sides ORDER BY COUNT(*) DESC, die.sides LIMIT $3"#,
+        user_id,
+        guild_id,
+        LUCK_SIZES
+    )
+    .fetch_all(postgres)
+    .await?;
+
+    let mut embed = CreateEmbed::new()
+        .colour(Colour::from_rgb(231, 41, 57))
+        .title(format!("{}'s Rolls", user.name))
+        .description(rows(rolls.iter().map(|roll| {
+            format!(
+                "<t:{}:R> `{}` = **{}** (seed {})",
+                roll.rolled_at.timestamp(),
+                truncate(&roll.expression, MAX_EXPRESSION_LEN),
+                roll.total,
+                roll.seed
+            )
+        })));
+    if !luck.is_empty() {
+        embed = embed.field(
+            "Luck",
+            luck.iter()
+                .map(|die| {
+                    format!(
+                        "d{}: averaged {:.2} over {} dice, expected {:.2}",
+                        die.sides,
+                        die.average,
+                        die.count,
+                        f64::from(die.sides + 1) / 2.
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            false,
+        );
+    }
+    ctx.send(CreateReply::new().embed(embed)).await?;
+
+    Ok(())
+}
+
+/// Join `rows` into lines, leaving off whichever don't fit in an embed description.
+fn rows(rows: impl IntoIterator<Item = String>) -> String {
+    let mut description = String::new();
+    let mut len = 0;
+    for row in rows {
+        let row_len = row.chars().count() + usize::from(len > 0);
+        if len + row_len > MAX_DESCRIPTION_LEN {
+            break;
+        }
+        if len > 0 {
+            description.push('\n');
+        }
+        description.push_str(&row);
+        len += row_len;
+    }
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rows, MAX_DESCRIPTION_LEN};
+
+    #[test]
+    fn rows_fit() {
+        assert_eq!(rows(["a".to_string(), "b".to_string()]), "a\nb");
+        let long = rows((0..25).map(|_| "x".repeat(200)));
+        assert_eq!(long.chars().count(), 20 * 201 - 1);
+        assert!(long.chars().count() <= MAX_DESCRIPTION_LEN);
+        assert_eq!(rows(["x".repeat(MAX_DESCRIPTION_LEN + 1)]), "");
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use poise::CreateReply;
+use serenity::all::{Colour, CreateEmbed, UserId};
+use sqlx::{query, PgPool};
+
+use super::{ensure, roll_expression, shunt, RollErrorKind, RollLimits, RollResult};
+use crate::Context;
+
+/// Longest a macro name can be.
+const MAX_NAME_LEN: usize = 32;
+/// How many macros deep a macro can refer to other macros.
+const MAX_DEPTH: usize = 16;
+
+/// Every macro the user with `user_id` has saved, by name.
+pub async fn fetch_all(user_id: UserId, postgres: &PgPool) -> Result<HashMap<String, String>> {
+    let user_id: i64 = user_id.get().try_into()?;
+
+    Ok(query!(
+        "SELECT name, expression FROM roll_macro WHERE user_id = $1",
+        user_id
+    )
+    .fetch_all(postgres)
+    .await?
+    .into_iter()
+    .map(|row| (row.name, row.expression))
+    .collect())
+}
+
+/// Replace every `@name` in `expression` with the macro it names, in parentheses.
+pub fn expand(
+    expression: &str,
+    macros: &HashMap<String, String>,
+    limits: &RollLimits,
+) -> RollResult<String> {
+    let mut expanded = String::new();
+    expand_into(expression, macros, limits, &mut Vec::new(), &mut expanded)?;
+    Ok(expanded)
+}
+
+fn expand_into(
+    expression: &str,
+    macros: &HashMap<String, String>,
+    limits: &RollLimits,
+    expanding: &mut Vec<String>,
+    out: &mut String,
+) -> RollResult<()> {
+    let mut chars = expression.chars().peekable();
+    while let Some(char) = chars.next() {
+        if char != '@' {
+            out.push(char);
+            continue;
+        }
+
+        let mut name = String::new();
+        while let Some(char) = chars.next_if(|char| is_name_char(*char)) {
+            name.push(char.to_ascii_lowercase());
+        }
+        ensure(
+            !expanding.contains(&name),
+            RollErrorKind::RecursiveMacro(name.clone()),
+        )?;
+        ensure(
+            expanding.len() < MAX_DEPTH,
+            RollErrorKind::RecursiveMacro(name.clone()),
+        )?;
+        let body = macros
+            .get(&name)
+            .ok_or_else(|| RollErrorKind::UnknownMacro(name.clone()))?;
+
+        expanding.push(name);
+        out.push('(');
+        expand_into(body, macros, limits, expanding, out)?;
+        out.push(')');
+        expanding.pop();
+
+        // stop before macros that use each other several times blow up
+        ensure(
+            out.chars().count() <= limits.max_length,
+            RollErrorKind::TooLong(limits.max_length),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn is_name_char(char: char) -> bool {
+    char.is_ascii_alphanumeric() || char == '_'
+}
+
+/// Save a roll to use later as @name
+#[poise::command(slash_command)]
+pub async fn save(
+    ctx: Context<'_>,
+    #[description = "Name to save the roll as"] name: String,
+    #[description = "What to roll, can use other saved rolls as @name"] expr: String,
+) -> Result<()> {
+    let data = ctx.data();
+    let name = name.trim_start_matches('@').to_ascii_lowercase();
+    ensure(
+        !name.is_empty() && name.len() <= MAX_NAME_LEN && name.chars().all(is_name_char),
+        RollErrorKind::InvalidMacroName(MAX_NAME_LEN),
+    )?;
+
+    // make sure the macro works before saving it, without rolling anything
+    let mut macros = fetch_all(ctx.author().id, &data.postgres).await?;
+    macros.insert(name.clone(), expr.clone());
+    let expanded = expand(&format!("@{name}"), &macros, &data.roll_limits)?;
+    shunt(&expanded, &data.roll_limits).map_err(|error| error.in_expression(&expanded))?;
+
+    let user_id: i64 = ctx.author().id.get().try_into()?;
+    query!(
+        "INSERT INTO roll_macro(user_id, name, expression) VALUES($1, $2, $3)
+        ON CONFLICT (user_id, name) DO UPDATE SET expression = EXCLUDED.expression",
+        user_id,
+        name,
+        expr
+    )
+    .execute(&data.postgres)
+    .await?;
+
+    ctx.send(
+        CreateReply::new()
+            .ephemeral(true)
+            .content(format!("Saved `{expr}` as `@{name}`.")),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// List your saved rolls
+#[poise::command(slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<()> {
+    let macros = fetch_all(ctx.author().id, &ctx.data().postgres).await?;
+
+    if macros.is_empty() {
+        ctx.send(
+            CreateReply::new()
+                .ephemeral(true)
+                .content("You haven't saved any rolls yet. Save one with `/roll save`."),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let mut macros: Vec<(String, String)> = macros.into_iter().collect();
+    macros.sort_unstable();
+    ctx.send(
+        CreateReply::new().ephemeral(true).embed(
+            CreateEmbed::new()
+                .colour(Colour::from_rgb(231, 41, 57))
+                .title("Saved Rolls")
+                .description(
+                    macros
+                        .iter()
+                        .map(|(name, expression)| format!("`@{name}`: `{expression}`"))
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                ),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Delete a saved roll
+#[poise::command(slash_command)]
+pub async fn delete(
+    ctx: Context<'_>,
+    #[description = "Name of the roll to delete"] name: String,
+) -> Result<()> {
+    let name = name.trim_start_matches('@').to_ascii_lowercase();
+    let user_id: i64 = ctx.author().id.get().try_into()?;
+
+    let deleted = query!(
+        "DELETE FROM roll_macro WHERE user_id = $1 AND name = $2",
+        user_id,
+        name
+    )
+    .execute(&ctx.data().postgres)
+    .await?
+    .rows_affected();
+
+    ctx.send(CreateReply::new().ephemeral(true).content(if deleted > 0 {
+        format!("Deleted `@{name}`.")
+    } else {
+        format!("You don't have a saved roll called `@{name}`.")
+    }))
+    .await?;
+
+    Ok(())
+}
+
+/// Roll a saved roll
+#[poise::command(slash_command)]
+pub async fn run(
+    ctx: Context<'_>,
+    #[description = "Name of the roll to roll"] name: String,
+) -> Result<()> {
+    roll_expression(ctx, &format!("@{}", name.trim_start_matches('@')), None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{expand, RollErrorKind, RollLimits};
+
+    fn macros() -> HashMap<String, String> {
+        [
+            ("str", "4"),
+            ("attack", "d20 + @str + @prof"),
+            ("prof", "2"),
+            ("loop", "1 + @pool"),
+            ("pool", "@loop"),
+            ("twice", "@attack + @attack"),
+        ]
+        .into_iter()
+        .map(|(name, expression)| (name.to_string(), expression.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn nested() {
+        assert_eq!(
+            expand("@Attack * 2", &macros(), &RollLimits::default()).unwrap(),
+            "(d20 + (4) + (2)) * 2"
+        );
+        assert_eq!(
+            expand("@twice", &macros(), &RollLimits::default()).unwrap(),
+            "((d20 + (4) + (2)) + (d20 + (4) + (2)))"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let limits = RollLimits::default();
+        assert!(matches!(
+            expand("@loop", &macros(), &limits).unwrap_err().kind,
+            RollErrorKind::RecursiveMacro(_)
+        ));
+        assert!(matches!(
+            expand("@dex", &macros(), &limits).unwrap_err().kind,
+            RollErrorKind::UnknownMacro(_)
+        ));
+
+        let limits = RollLimits {
+            max_length: 20,
+            ..RollLimits::default()
+        };
+        assert!(matches!(
+            expand("@twice", &macros(), &limits).unwrap_err().kind,
+            RollErrorKind::TooLong(_)
+        ));
+    }
+}
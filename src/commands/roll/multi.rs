@@ -0,0 +1,186 @@
+use super::{ensure, Outcome, RollErrorKind, RollLimits, RollResult, Token};
+
+/// One `;`-separated part of a roll command, like `6x 4d6kh3 stats`.
+#[derive(Debug, PartialEq)]
+pub struct Part<'a> {
+    pub expression: &'a str,
+    pub label: Option<&'a str>,
+    /// How many times to roll `expression`.
+    pub repeat: usize,
+}
+
+impl<'a> Part<'a> {
+    fn parse(part: &'a str) -> Self {
+        let digits = part
+            .find(|char: char| !char.is_ascii_digit())
+            .unwrap_or(part.len());
+        let (repeat, rest) = match part[digits..].strip_prefix(|char| char == 'x' || char == 'X') {
+            Some(rest) if digits > 0 => (part[..digits].parse().unwrap_or(usize::MAX), rest),
+            _ => (1, part),
+        };
+
+        // words at the end with no numbers or symbols can't be part of a roll
+        let rest = rest.trim();
+        let mut expression = rest;
+        while let Some((before, word)) = expression.rsplit_once(char::is_whitespace) {
+            if !word
+                .chars()
+                .all(|char| char.is_alphabetic() || "'-_".contains(char))
+            {
+                break;
+            }
+            expression = before.trim_end();
+        }
+        let label = rest[expression.len()..].trim();
+
+        Self {
+            expression,
+            label: (!label.is_empty()).then_some(label),
+            repeat,
+        }
+    }
+
+    /// How the `index`th roll of this part is named in a reply.
+    fn name(&self, index: usize) -> String {
+        let mut name = format!("`{}`", self.expression);
+        if let Some(label) = self.label {
+            name = format!("{label} {name}");
+        }
+        if self.repeat > 1 {
+            name = format!("{name} #{}", index + 1);
+        }
+        name
+    }
+}
+
+/// Split `input` into its parts.
+pub fn parse<'a>(input: &'a str, limits: &RollLimits) -> RollResult<Vec<Part<'a>>> {
+    let parts: Vec<Part> = input
+        .split(';')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(Part::parse)
+        .collect();
+
+    ensure(
+        !parts.is_empty() && parts.iter().all(|part| part.repeat > 0),
+        RollErrorKind::InvalidExpression,
+    )?;
+    let rolls = parts
+        .iter()
+        .fold(0usize, |rolls, part| rolls.saturating_add(part.repeat));
+    ensure(
+        rolls <= limits.max_rolls,
+        RollErrorKind::TooManyRolls(limits.max_rolls),
+    )?;
+
+    Ok(parts)
+}
+
+/// Every roll's total added up.
+pub fn grand_total(outcomes: &[Outcome]) -> RollResult<Token> {
+    outcomes.iter().try_fold(Token::Int(0), |total, outcome| {
+        total.add(outcome.total.clone())
+    })
+}
+
+/// The reply to rolling `parts`, where `outcomes` has every roll of each part in order.
+/// `total` is shown if there's more than one part, and breakdowns are left out if `brief`.
+pub fn reply(parts: &[Part], outcomes: &[Outcome], total: &Token, brief: bool) -> String {
+    let mut outcomes = outcomes.iter();
+    let mut lines = Vec::new();
+    for part in parts {
+        for (index, outcome) in outcomes.by_ref().take(part.repeat).enumerate() {
+            let name = part.name(index);
+            lines.push(match brief {
+                true => format!("{name}: **{}**", outcome.total),
+                false => format!("{name}: {} = **{}**", outcome.breakdown, outcome.total),
+            });
+        }
+    }
+    if parts.len() > 1 {
+        lines.push(format!("Total: **{total}**"));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grand_total, reply, Outcome, Part, RollErrorKind, RollLimits, RollResult, Token};
+
+    fn parse(input: &str) -> RollResult<Vec<Part<'_>>> {
+        super::parse(input, &RollLimits::default())
+    }
+
+    fn part<'a>(expression: &'a str, label: Option<&'a str>, repeat: usize) -> Part<'a> {
+        Part {
+            expression,
+            label,
+            repeat,
+        }
+    }
+
+    fn outcome(total: i32, breakdown: &str) -> Outcome {
+        Outcome {
+            total: Token::Int(total),
+            breakdown: breakdown.to_string(),
+            dice: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parts() {
+        assert_eq!(parse("d20 + 5").unwrap(), [part("d20 + 5", None, 1)]);
+        assert_eq!(parse("6x 4d6kh3").unwrap(), [part("4d6kh3", None, 6)]);
+        assert_eq!(
+            parse("2d6+3 fire; 1d8 slashing damage;").unwrap(),
+            [
+                part("2d6+3", Some("fire"), 1),
+                part("1d8", Some("slashing damage"), 1)
+            ]
+        );
+        assert_eq!(
+            parse("2X@attack sneak-attack").unwrap(),
+            [part("@attack", Some("sneak-attack"), 2)]
+        );
+    }
+
+    #[test]
+    fn invalid_parts() {
+        let error = |input| parse(input).unwrap_err().kind;
+        assert!(matches!(error("21x d6"), RollErrorKind::TooManyRolls(_)));
+        assert!(matches!(
+            error("99999999999999999999999x d6"),
+            RollErrorKind::TooManyRolls(_)
+        ));
+        assert!(matches!(error("0x d6"), RollErrorKind::InvalidExpression));
+        let limits = RollLimits {
+            max_rolls: 4,
+            ..RollLimits::default()
+        };
+        assert!(matches!(
+            super::parse("5x d6", &limits).unwrap_err().kind,
+            RollErrorKind::TooManyRolls(4)
+        ));
+        assert!(matches!(error(" ; "), RollErrorKind::InvalidExpression));
+    }
+
+    #[test]
+    fn replies() {
+        let outcomes = [outcome(11, "[3, 5] + 3"), outcome(6, "[6]")];
+        let total = grand_total(&outcomes).unwrap();
+        assert_eq!(total, Token::Int(17));
+
+        let parts = [part("2d6+3", Some("fire"), 1), part("1d8", None, 1)];
+        assert_eq!(
+            reply(&parts, &outcomes, &total, false),
+            "fire `2d6+3`: [3, 5] + 3 = **11**\n`1d8`: [6] = **6**\nTotal: **17**"
+        );
+
+        let parts = [part("d8", None, 2)];
+        assert_eq!(
+            reply(&parts, &outcomes, &total, true),
+            "`d8` #1: **11**\n`d8` #2: **6**"
+        );
+    }
+}
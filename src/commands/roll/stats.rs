@@ -0,0 +1,748 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use poise::CreateReply;
+use rand::Rng;
+use serenity::all::{Colour, CreateEmbed};
+
+use super::{
+    eval, expand_macros, shunt, Operator, RollError, RollErrorKind, RollLimits, RollResult, Token,
+    MAX_EXPLOSIONS,
+};
+use crate::commands::truncate;
+use crate::Context;
+
+/// Roughly how many steps working out a distribution exactly can take before sampling is used instead.
+const MAX_WORK: u64 = 50_000_000;
+/// Most times a roll is made when its distribution is estimated.
+const SAMPLES: usize = 20_000;
+/// Fewest times a roll is made when its distribution is estimated, however long it takes.
+const MIN_SAMPLES: usize = 1_000;
+/// How long to spend sampling once `MIN_SAMPLES` rolls have been made.
+const SAMPLE_TIME: Duration = Duration::from_secs(2);
+/// Chance below which an exploding die is treated as having stopped.
+const EPSILON: f64 = 1e-12;
+/// Chance of the rarest totals at each end that the histogram lumps together.
+const TAIL: f64 = 0.0005;
+const MAX_ROWS: usize = 20;
+/// Most characters Discord allows in an embed title.
+const MAX_TITLE_LEN: usize = 256;
+const BAR_WIDTH: usize = 20;
+
+/// Show the odds of a roll
+#[poise::command(slash_command)]
+pub async fn stats(
+    ctx: Context<'_>,
+    #[description = "What to roll, can use saved rolls as @name"] expr: String,
+) -> Result<()> {
+    let expanded = expand_macros(ctx, &expr).await?;
+    let limits = ctx.data().roll_limits.clone();
+    let mut rng = ctx.data().rng.next();
+    let tokens = shunt(&expanded, &limits).map_err(|error| error.in_expression(&expanded))?;
+    let stats = tokio::task::spawn_blocking(move || Stats::of(tokens, &limits, &mut rng))
+        .await?
+        .map_err(|error| error.in_expression(&expanded))?;
+
+    let method = match stats.samples {
+        Some(samples) => format!("Estimated from {samples} rolls"),
+        None => "Exact".to_string(),
+    };
+    ctx.send(
+        CreateReply::new().embed(
+            CreateEmbed::new()
+                .colour(Colour::from_rgb(231, 41, 57))
+                .title(title(&expr))
+                .description(format!("```\n{}\n```", stats.histogram()))
+                .field("Min", stats.min.to_string(), true)
+                .field("Max", stats.max.to_string(), true)
+                .field("Mean", format!("{:.2}", stats.mean), true)
+                .field("Standard Deviation", format!("{:.2}", stats.std_dev), true)
+                .field("Method", method, true),
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The embed title for the odds of `expr`, shortened to fit.
+fn title(expr: &str) -> String {
+    truncate(&format!("Odds of {expr}"), MAX_TITLE_LEN)
+}
+
+/// The distribution of a roll's total.
+pub struct Stats {
+    /// Each total and its probability, lowest total first.
+    outcomes: Vec<(f64, f64)>,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub std_dev: f64,
+    /// How many rolls were sampled, or `None` if the distribution is exact.
+    pub samples: Option<usize>,
+}
+
+impl Stats {
+    /// Work out the distribution of `tokens`, exactly if that's cheap enough or by sampling otherwise.
+    pub fn of(
+        tokens: Vec<(Token, usize)>,
+        limits: &RollLimits,
+        rng: &mut impl Rng,
+    ) -> RollResult<Self> {
+        let mut work = MAX_WORK;
+        let (outcomes, samples) = match exact(&tokens, limits, &mut work)? {
+            Some(dist) => (
+                dist.outcomes()
+                    .map(|(total, chance)| (total as f64, chance))
+                    .collect(),
+                None,
+            ),
+            None => {
+                let (outcomes, samples) = sample(tokens, limits, rng)?;
+                (outcomes, Some(samples))
+            }
+        };
+
+        let mean: f64 = outcomes.iter().map(|(total, chance)| total * chance).sum();
+        let variance: f64 = outcomes
+            .iter()
+            .map(|(total, chance)| (total - mean).powi(2) * chance)
+            .sum();
+        Ok(Self {
+            min: outcomes.first().map_or(0., |(total, _)| *total),
+            max: outcomes.last().map_or(0., |(total, _)| *total),
+            mean,
+            std_dev: variance.sqrt(),
+            samples,
+            outcomes,
+        })
+    }
+
+    /// A bar chart of the distribution, grouping totals into ranges if there are too many to list.
+    pub fn histogram(&self) -> String {
+        let rows = match self.outcomes.len() <= MAX_ROWS {
+            true => self
+                .outcomes
+                .iter()
+                .map(|(total, chance)| (total.to_string(), *chance))
+                .collect(),
+            false => self.grouped_rows(),
+        };
+
+        let label_width = rows
+            .iter()
+            .map(|(label, _)| label.chars().count())
+            .max()
+            .unwrap_or(0);
+        let most_likely = rows.iter().map(|(_, chance)| *chance).fold(0., f64::max);
+        rows.iter()
+            .map(|(label, chance)| {
+                let bar = (chance / most_likely * BAR_WIDTH as f64).round() as usize;
+                format!(
+                    "{label:>label_width$} | {:<BAR_WIDTH$} {:5.2}%",
+                    "█".repeat(bar),
+                    chance * 100.
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Totals grouped into at most `MAX_ROWS` equal ranges, with the rarest totals at each end lumped into the
+    /// first and last.
+    fn grouped_rows(&self) -> Vec<(String, f64)> {
+        let low = self.quantile(TAIL);
+        let high = self.quantile(1. - TAIL);
+        let whole = self.outcomes.iter().all(|(total, _)| total.fract() == 0.);
+        let (width, rows) = match whole {
+            true => {
+                let width = ((high - low + 1.) / MAX_ROWS as f64).ceil();
+                (width, ((high - low + 1.) / width).ceil() as usize)
+            }
+            false => (
+                ((high - low) / MAX_ROWS as f64).max(f64::MIN_POSITIVE),
+                MAX_ROWS,
+            ),
+        };
+
+        let mut chances = vec![0.; rows];
+        for (total, chance) in &self.outcomes {
+            let row = ((total - low) / width).max(0.) as usize;
+            chances[row.min(rows - 1)] += chance;
+        }
+
+        chances
+            .into_iter()
+            .enumerate()
+            .map(|(row, chance)| {
+                let start = low + width * row as f64;
+                let end = match whole {
+                    true => start + width - 1.,
+                    false => start + width,
+                };
+                let label = if row == 0 && self.min < start {
+                    format!("≤{end:.0$}", !whole as usize)
+                } else if row == rows - 1 && self.max > end {
+                    format!("≥{start:.0$}", !whole as usize)
+                } else if start == end {
+                    start.to_string()
+                } else {
+                    format!("{start:.0$}-{end:.0$}", !whole as usize)
+                };
+                (label, chance)
+            })
+            .collect()
+    }
+
+    /// The lowest total with at least `chance` of rolling it or lower.
+    fn quantile(&self, chance: f64) -> f64 {
+        let mut below = 0.;
+        for (total, total_chance) in &self.outcomes {
+            below += total_chance;
+            if below >= chance {
+                return *total;
+            }
+        }
+        self.max
+    }
+}
+
+/// Make the roll as many times as there's time for and count how often each total comes up.
+fn sample(
+    tokens: Vec<(Token, usize)>,
+    limits: &RollLimits,
+    rng: &mut impl Rng,
+) -> RollResult<(Vec<(f64, f64)>, usize)> {
+    let start = Instant::now();
+    let mut totals = Vec::with_capacity(SAMPLES);
+    while totals.len() < SAMPLES && (totals.len() < MIN_SAMPLES || start.elapsed() < SAMPLE_TIME) {
+        totals.push(match eval(tokens.clone(), limits, rng)?.total {
+            Token::Float(total) => total as f64,
+            total => total.int()? as f64,
+        });
+    }
+    totals.sort_unstable_by(f64::total_cmp);
+
+    let mut outcomes: Vec<(f64, f64)> = Vec::new();
+    for total in &totals {
+        match outcomes.last_mut() {
+            Some((last, chance)) if last == total => *chance += 1.,
+            _ => outcomes.push((*total, 1.)),
+        }
+    }
+    for (_, chance) in outcomes.iter_mut() {
+        *chance /= totals.len() as f64;
+    }
+    Ok((outcomes, totals.len()))
+}
+
+/// Take `cost` steps from `work`, or `None` if there aren't enough left.
+fn spend(work: &mut u64, cost: u64) -> Option<()> {
+    *work = work.checked_sub(cost)?;
+    Some(())
+}
+
+/// Probability of each total a roll can have, starting from `low`.
+#[derive(Clone, Debug)]
+struct Dist {
+    low: i64,
+    chances: Vec<f64>,
+}
+
+impl Dist {
+    fn constant(value: i64) -> Self {
+        Self {
+            low: value,
+            chances: vec![1.],
+        }
+    }
+
+    /// Each side of a `sides`-sided die, shifted by `offset`, with equal chance.
+    fn uniform(sides: i64, offset: i64) -> Self {
+        Self {
+            low: offset + 1,
+            chances: vec![1. / sides as f64; sides as usize],
+        }
+    }
+
+    /// Collect `outcomes` into a distribution, adding up the chances of totals that come up more than once.
+    fn from_outcomes(outcomes: Vec<(i64, f64)>, work: &mut u64) -> Option<Self> {
+        let low = outcomes.iter().map(|(total, _)| *total).min()?;
+        let high = outcomes.iter().map(|(total, _)| *total).max()?;
+        let len = usize::try_from(high.checked_sub(low)?.checked_add(1)?).ok()?;
+        spend(work, len as u64)?;
+
+        let mut chances = vec![0.; len];
+        for (total, chance) in outcomes {
+            chances[(total - low) as usize] += chance;
+        }
+        Some(Self { low, chances })
+    }
+
+    /// Each total that can come up, with its chance.
+    fn outcomes(&self) -> impl Iterator<Item = (i64, f64)> + '_ {
+        self.chances
+            .iter()
+            .enumerate()
+            .filter(|(_, chance)| **chance > 0.)
+            .map(|(offset, chance)| (self.low + offset as i64, *chance))
+    }
+
+    /// Whether every total fits in the `i32` rolls are made in.
+    fn fits_i32(&self) -> bool {
+        let high = self.low + self.chances.len() as i64 - 1;
+        self.low >= i32::MIN as i64 && high <= i32::MAX as i64
+    }
+
+    /// The only total this can be, if there's just one.
+    fn constant_value(&self) -> Option<i64> {
+        let mut outcomes = self.outcomes();
+        let (total, _) = outcomes.next()?;
+        outcomes.next().is_none().then_some(total)
+    }
+
+    fn map(&self, apply: impl Fn(i64) -> i64, work: &mut u64) -> Option<Self> {
+        Self::from_outcomes(
+            self.outcomes()
+                .map(|(total, chance)| (apply(total), chance))
+                .collect(),
+            work,
+        )
+    }
+
+    /// Distribution of adding a total from `self` to one from `other`.
+    fn add(&self, other: &Self, work: &mut u64) -> Option<Self> {
+        spend(
+            work,
+            (self.chances.len() as u64).checked_mul(other.chances.len() as u64)?,
+        )?;
+
+        let mut chances = vec![0.; self.chances.len() + other.chances.len() - 1];
+        for (lhs, lhs_chance) in self.chances.iter().enumerate() {
+            for (rhs, rhs_chance) in other.chances.iter().enumerate() {
+                chances[lhs + rhs] += lhs_chance * rhs_chance;
+            }
+        }
+        Some(Self {
+            low: self.low.checked_add(other.low)?,
+            chances,
+        })
+    }
+
+    /// Distribution of multiplying a total from `self` by one from `other`.
+    fn mul(&self, other: &Self, work: &mut u64) -> Option<Self> {
+        spend(
+            work,
+            (self.chances.len() as u64).checked_mul(other.chances.len() as u64)?,
+        )?;
+
+        let mut outcomes = BTreeMap::new();
+        for (lhs, lhs_chance) in self.outcomes() {
+            for (rhs, rhs_chance) in other.outcomes() {
+                *outcomes.entry(lhs.checked_mul(rhs)?).or_default() += lhs_chance * rhs_chance;
+            }
+        }
+        Self::from_outcomes(outcomes.into_iter().collect(), work)
+    }
+}
+
+/// A set of dice that haven't been added up yet, so operators like `kh` can still apply to them.
+#[derive(Clone)]
+struct Pool {
+    count: usize,
+    sides: i64,
+    /// Distribution of a single die after rerolls and compounding.
+    die: Dist,
+    /// Whether dice exploded into extra dice, which only adding them up handles exactly.
+    exploded: bool,
+    /// How many dice are kept, and whether they're the highest.
+    keep: Option<(usize, bool)>,
+    success: Option<i64>,
+    failure: Option<i64>,
+}
+
+enum Value {
+    Dist(Dist),
+    Pool(Pool),
+}
+
+impl Value {
+    fn into_dist(self, work: &mut u64) -> Option<Dist> {
+        match self {
+            Value::Dist(dist) => Some(dist),
+            Value::Pool(pool) => pool.into_dist(work),
+        }
+    }
+
+    fn constant(self, work: &mut u64) -> Option<i64> {
+        self.into_dist(work)?.constant_value()
+    }
+
+    fn pool(self) -> Option<Pool> {
+        match self {
+            Value::Pool(pool) => Some(pool),
+            Value::Dist(_) => None,
+        }
+    }
+}
+
+/// Work out the distribution of `tokens` exactly, or `None` if that's too much work or not possible.
+/// Anything else that would fail to roll is `None` too, so sampling reports the error.
+/// Totals that can't be rolled without overflowing are an error here, as they might not come up when sampling.
+fn exact(
+    tokens: &[(Token, usize)],
+    limits: &RollLimits,
+    work: &mut u64,
+) -> RollResult<Option<Dist>> {
+    let mut stack: Vec<Value> = Vec::new();
+    let overflow = |position: usize| RollError::from(RollErrorKind::Overflow).at(position);
+
+    for (token, position) in tokens {
+        let Some(value) = step(token, &mut stack, limits, work) else {
+            return Ok(None);
+        };
+        if matches!(&value, Value::Dist(dist) if !dist.fits_i32()) {
+            return Err(overflow(*position));
+        }
+        stack.push(value);
+    }
+
+    match (stack.pop(), stack.is_empty()) {
+        (Some(value), true) => match value.into_dist(work) {
+            Some(dist) if !dist.fits_i32() => {
+                Err(overflow(tokens.last().map_or(0, |(_, position)| *position)))
+            }
+            dist => Ok(dist),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Apply `token` to the values on `stack`, returning the value it makes.
+fn step(
+    token: &Token,
+    stack: &mut Vec<Value>,
+    limits: &RollLimits,
+    work: &mut u64,
+) -> Option<Value> {
+    let operator = match token {
+        Token::Int(n) => return Some(Value::Dist(Dist::constant(*n as i64))),
+        Token::Op(operator) => operator,
+        _ => return None,
+    };
+
+    Some(match operator {
+        Operator::Neg => Value::Dist(stack.pop()?.into_dist(work)?.map(|total| -total, work)?),
+        Operator::Explode | Operator::Compound => {
+            let mut pool = stack.pop()?.pool()?;
+            if pool.keep.is_some() || pool.success.is_some() || pool.sides < 2 {
+                return None;
+            }
+            pool.die = compound(&pool.die, pool.sides, work)?;
+            pool.exploded |= *operator == Operator::Explode;
+            Value::Pool(pool)
+        }
+        _ => {
+            let rhs = stack.pop()?;
+            let lhs = stack.pop()?;
+            binary(operator, lhs, rhs, limits, work)?
+        }
+    })
+}
+
+fn binary(
+    operator: &Operator,
+    lhs: Value,
+    rhs: Value,
+    limits: &RollLimits,
+    work: &mut u64,
+) -> Option<Value> {
+    match operator {
+        Operator::Dice => {
+            let count = usize::try_from(lhs.constant(work)?).ok()?;
+            let sides = rhs.constant(work)?;
+            if count == 0 || count > limits.max_dice || sides < 1 || sides > limits.max_sides as i64
+            {
+                return None;
+            }
+            spend(work, sides as u64)?;
+            Some(Value::Pool(Pool {
+                count,
+                sides,
+                die: Dist::uniform(sides, 0),
+                exploded: false,
+                keep: None,
+                success: None,
+                failure: None,
+            }))
+        }
+        Operator::KeepHighest
+        | Operator::KeepLowest
+        | Operator::DropHighest
+        | Operator::DropLowest => {
+            let mut pool = lhs.pool()?;
+            let n = usize::try_from(rhs.constant(work)?).ok()?;
+            if pool.keep.is_some() || pool.exploded {
+                return None;
+            }
+            pool.keep = Some(match operator {
+                Operator::KeepHighest => (n, true),
+                Operator::KeepLowest => (n, false),
+                Operator::DropHighest => (pool.count.saturating_sub(n), false),
+                _ => (pool.count.saturating_sub(n), true),
+            });
+            Some(Value::Pool(pool))
+        }
+        Operator::Reroll | Operator::RerollOnce => {
+            let mut pool = lhs.pool()?;
+            let n = rhs.constant(work)?;
+            let once = *operator == Operator::RerollOnce;
+            if pool.keep.is_some() || pool.success.is_some() || pool.exploded {
+                return None;
+            }
+            if !once && n >= pool.sides {
+                return None;
+            }
+            pool.die = reroll(&pool.die, pool.sides, n, once, work)?;
+            Some(Value::Pool(pool))
+        }
+        Operator::Successes => {
+            let mut pool = lhs.pool()?;
+            if pool.exploded {
+                return None;
+            }
+            pool.success = Some(rhs.constant(work)?);
+            Some(Value::Pool(pool))
+        }
+        Operator::Failures => {
+            let mut pool = lhs.pool()?;
+            pool.success?;
+            pool.failure = Some(rhs.constant(work)?);
+            Some(Value::Pool(pool))
+        }
+        Operator::Add => Some(Value::Dist(
+            lhs.into_dist(work)?.add(&rhs.into_dist(work)?, work)?,
+        )),
+        Operator::Sub => {
+            let rhs = rhs.into_dist(work)?.map(|total| -total, work)?;
+            Some(Value::Dist(lhs.into_dist(work)?.add(&rhs, work)?))
+        }
+        Operator::Mul => Some(Value::Dist(
+            lhs.into_dist(work)?.mul(&rhs.into_dist(work)?, work)?,
+        )),
+        _ => None,
+    }
+}
+
+impl Pool {
+    /// What a die showing `value` adds to the total.
+    fn score(&self, value: i64) -> i64 {
+        match self.success {
+            Some(success) if value >= success => 1,
+            Some(_) if self.failure.is_some_and(|failure| value <= failure) => -1,
+            Some(_) => 0,
+            None => value,
+        }
+    }
+
+    fn into_dist(self, work: &mut u64) -> Option<Dist> {
+        match self.keep {
+            None => {
+                let scores = self.die.map(|value| self.score(value), work)?;
+                let mut total = Dist::constant(0);
+                for _ in 0..self.count {
+                    total = total.add(&scores, work)?;
+                }
+                Some(total)
+            }
+            Some((keep, highest)) => self.enumerate(keep, highest, work),
+        }
+    }
+
+    /// Go through every way the dice can land, adding up the kept ones.
+    fn enumerate(&self, keep: usize, highest: bool, work: &mut u64) -> Option<Dist> {
+        let faces: Vec<(i64, f64)> = self.die.outcomes().collect();
+        let combinations = (faces.len() as u64).checked_pow(self.count.try_into().ok()?)?;
+        spend(work, combinations.checked_mul(self.count as u64)?)?;
+
+        let mut totals = BTreeMap::new();
+        let mut faces_shown = vec![0; self.count];
+        let mut values = Vec::with_capacity(self.count);
+        loop {
+            values.clear();
+            values.extend(faces_shown.iter().map(|face| faces[*face].0));
+            values.sort_unstable();
+            if highest {
+                values.reverse();
+            }
+            let score: i64 = values
+                .iter()
+                .take(keep)
+                .map(|value| self.score(*value))
+                .sum();
+            let chance: f64 = faces_shown.iter().map(|face| faces[*face].1).product();
+            *totals.entry(score).or_default() += chance;
+
+            // move on to the next combination, like an odometer
+            let Some(die) = faces_shown.iter().position(|face| face + 1 < faces.len()) else {
+                return Dist::from_outcomes(totals.into_iter().collect(), work);
+            };
+            faces_shown[die] += 1;
+            faces_shown[..die].fill(0);
+        }
+    }
+}
+
+/// A die from `die` where showing `sides` adds another roll, which can do the same.
+fn compound(die: &Dist, sides: i64, work: &mut u64) -> Option<Dist> {
+    // what a fresh die adds once it has exploded
+    let mut extra = Vec::new();
+    let mut chance = 1.;
+    for explosion in 0..MAX_EXPLOSIONS as i64 {
+        spend(work, sides as u64)?;
+        for side in 1..sides {
+            extra.push((explosion * sides + side, chance / sides as f64));
+        }
+        chance /= sides as f64;
+        if chance < EPSILON {
+            break;
+        }
+    }
+
+    let mut outcomes = Vec::new();
+    for (value, value_chance) in die.outcomes() {
+        match value == sides {
+            true => outcomes.extend(
+                extra
+                    .iter()
+                    .map(|(added, added_chance)| (value + added, value_chance * added_chance)),
+            ),
+            false => outcomes.push((value, value_chance)),
+        }
+    }
+    Dist::from_outcomes(outcomes, work)
+}
+
+/// A die from `die` where showing `n` or lower is rerolled, until it's higher or only `once`.
+fn reroll(die: &Dist, sides: i64, n: i64, once: bool, work: &mut u64) -> Option<Dist> {
+    let rerolled: f64 = die
+        .outcomes()
+        .filter(|(value, _)| *value <= n)
+        .map(|(_, chance)| chance)
+        .sum();
+    let fresh = match once {
+        true => Dist::uniform(sides, 0),
+        false => Dist::uniform(sides - n.max(0), n.max(0)),
+    };
+
+    let outcomes = die
+        .outcomes()
+        .filter(|(value, _)| *value > n)
+        .chain(
+            fresh
+                .outcomes()
+                .map(|(value, chance)| (value, chance * rerolled)),
+        )
+        .collect();
+    Dist::from_outcomes(outcomes, work)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shunt, title, RollErrorKind, RollLimits, Stats};
+    use crate::rng::RngSource;
+
+    fn stats(input: &str) -> Stats {
+        let limits = RollLimits::default();
+        Stats::of(
+            shunt(input, &limits).unwrap(),
+            &limits,
+            &mut RngSource::rng(0),
+        )
+        .unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{actual} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn sum() {
+        let stats = stats("2d6 + 1");
+        assert_eq!(stats.samples, None);
+        assert_eq!((stats.min, stats.max), (3., 13.));
+        assert_close(stats.mean, 8., 1e-9);
+        assert_close(stats.std_dev, (35. / 6f64).sqrt(), 1e-9);
+        assert_close(stats.outcomes[5].1, 6. / 36., 1e-9);
+    }
+
+    #[test]
+    fn keep_highest() {
+        let stats = stats("4d6kh3");
+        assert_eq!(stats.samples, None);
+        assert_eq!((stats.min, stats.max), (3., 18.));
+        assert_close(stats.mean, 15869. / 1296., 1e-9);
+    }
+
+    #[test]
+    fn dice_modifiers() {
+        assert_close(stats("10d10>=8f<=1").mean, 2., 1e-9);
+        assert_close(stats("d6!!").mean, 4.2, 1e-6);
+        assert_close(stats("2d6!").mean, 8.4, 1e-6);
+        assert_close(stats("2d6r2").mean, 9., 1e-9);
+        assert_close(stats("d6ro1").mean, 3.5 + 2.5 / 6., 1e-9);
+        assert_close(stats("-(d4 * 2) - 1").mean, -6., 1e-9);
+    }
+
+    #[test]
+    fn overflow() {
+        let limits = RollLimits::default();
+        let error = |input| {
+            Stats::of(
+                shunt(input, &limits).unwrap(),
+                &limits,
+                &mut RngSource::rng(0),
+            )
+            .err()
+            .map(|error| error.kind)
+        };
+        assert!(matches!(
+            error("99999 * 99999"),
+            Some(RollErrorKind::Overflow)
+        ));
+        assert!(matches!(
+            error("2147483647 + d2"),
+            Some(RollErrorKind::Overflow)
+        ));
+        assert!(matches!(
+            error("0 - 2147483647 - d2"),
+            Some(RollErrorKind::Overflow)
+        ));
+        // overflowing part way through is an error even if the total would fit
+        assert!(matches!(
+            error("99999 * 99999 - 99999 * 99999"),
+            Some(RollErrorKind::Overflow)
+        ));
+        assert!(error("2147483645 + d2").is_none());
+    }
+
+    #[test]
+    fn titles() {
+        assert_eq!(title("2d6"), "Odds of 2d6");
+        let long = title(&"1+".repeat(200));
+        assert_eq!(long.chars().count(), super::MAX_TITLE_LEN);
+        assert!(long.ends_with('…'));
+    }
+
+    #[test]
+    fn sampled() {
+        let stats = stats("d20 / 2");
+        assert_eq!(stats.samples, Some(super::SAMPLES));
+        assert_close(stats.mean, 5.25, 0.15);
+        assert!(stats.histogram().lines().count() <= super::MAX_ROWS);
+    }
+}
@@ -6,16 +6,19 @@ use serenity::all::{
     ButtonStyle, CacheHttp, Colour, ComponentInteraction, CreateActionRow, CreateButton,
     CreateEmbed, EditInteractionResponse, UserId,
 };
-use sqlx::types::BitVec;
-use sqlx::{query, query_as, PgPool};
+use sqlx::{query, query_as, PgPool, Postgres, Transaction};
 
-use crate::commands::shares::perks::{FromName, PERKS};
 use crate::{Context, FrameworkContext};
+use give::give;
+use perks::respec;
 
+pub mod give;
 pub mod perks;
 
 pub const COLLECT_BUTTON: &str = "collect";
 pub const BUY_GENERATOR_BUTTON: &str = "buy_generator";
+pub const BUY_10_GENERATORS_BUTTON: &str = "buy_generator_10";
+pub const BUY_MAX_GENERATORS_BUTTON: &str = "buy_generator_max";
 pub const PRESTIGE_BUTTON: &str = "prestige";
 pub const PRESTIGE_CONFIRM_BUTTON: &str = "prestige_confirm";
 
@@ -28,7 +31,10 @@ struct Shares {
     prestige_count: i32,
     collection_time: Option<DateTime<Utc>>,
     generation_time: DateTime<Utc>,
-    perks: BitVec,
+    perk_ids: Vec<String>,
+    perk_levels: Vec<i32>,
+    respec_time: Option<DateTime<Utc>>,
+    joined_at: DateTime<Utc>,
 }
 
 impl Shares {
@@ -41,16 +47,92 @@ impl Shares {
         )
     }
 
+    /// Return `Shares` for user with `user_id`, locked until `transaction` ends.
+    async fn lock(user_id: i64, transaction: &mut Transaction<'_, Postgres>) -> Result<Self> {
+        Ok(query_as!(
+            Self,
+            "SELECT * FROM share WHERE user_id = $1 FOR UPDATE",
+            user_id
+        )
+        .fetch_one(&mut *transaction)
+        .await?)
+    }
+
+    /// Return up to date `Shares` for user with `user_id`, starting them off if they have none yet.
+    async fn fetch_updated(user_id: i64, postgres: &PgPool) -> Result<Self> {
+        let mut transaction = postgres.begin().await?;
+        query!(
+            "INSERT INTO share(user_id) VALUES($1) ON CONFLICT DO NOTHING",
+            user_id
+        )
+        .execute(&mut transaction)
+        .await?;
+        let mut shares = Self::lock(user_id, &mut transaction).await?;
+        shares.update(&mut transaction).await?;
+        transaction.commit().await?;
+
+        Ok(shares)
+    }
+
+    /// Return up to date `Shares` for every user.
+    async fn fetch_all_updated(postgres: &PgPool) -> Result<Vec<Self>> {
+        let mut transaction = postgres.begin().await?;
+        // always locking in the same order means this can't deadlock with itself
+        let mut shares_vec = query_as!(Self, "SELECT * FROM share ORDER BY user_id FOR UPDATE")
+            .fetch_all(&mut transaction)
+            .await?;
+        for shares in shares_vec.iter_mut() {
+            shares.update(&mut transaction).await?;
+        }
+        transaction.commit().await?;
+
+        Ok(shares_vec)
+    }
+
     /// The base amount of time (in seconds) until a share can be collected again or a generator runs once.
     const COLLECTION_COOLDOWN: i32 = 60 * 60;
 
+    /// Get how many times more each generator costs than the last.
+    fn generator_cost_ratio(&self) -> f32 {
+        self.perks()
+            .filter_map(|(perk, _)| perk.effect.generator_cost_ratio())
+            .fold(2., f32::min)
+    }
+
     /// Get the amount of shares it would take to make another generator.
     fn next_generator_cost(&self) -> f32 {
-        if self.perks[PERKS.world_is_mine()] {
-            1.6_f32.powi(self.generators)
+        self.generator_cost_ratio().powi(self.generators)
+    }
+
+    /// Get the amount of shares it would take to make `count` more generators.
+    fn generators_cost(&self, count: i32) -> f32 {
+        // costs go up geometrically, so they sum to r^n * (r^count - 1) / (r - 1)
+        let ratio = self.generator_cost_ratio();
+        if ratio <= 1. {
+            // a ratio that doesn't grow makes every generator cost the same
+            return self.next_generator_cost() * count as f32;
+        }
+        self.next_generator_cost() * (ratio.powi(count) - 1.) / (ratio - 1.)
+    }
+
+    /// Get the most generators that can be bought right now.
+    fn affordable_generators(&self) -> i32 {
+        let ratio = self.generator_cost_ratio();
+        let mut count = if ratio <= 1. {
+            (self.shares / self.next_generator_cost()).floor() as i32
         } else {
-            2_f32.powi(self.generators)
+            (self.shares * (ratio - 1.) / self.next_generator_cost() + 1.)
+                .log(ratio)
+                .floor() as i32
+        };
+        // rounding can leave the estimate off by one either way
+        while self.generators_cost(count + 1) <= self.shares {
+            count += 1;
         }
+        while count > 0 && self.generators_cost(count) > self.shares {
+            count -= 1;
+        }
+        count
     }
 
     /// Get the amount of shares it would take to perform a prestige reset.
@@ -60,22 +142,16 @@ impl Shares {
 
     /// Get the generator production multiplier from perks.
     fn generator_multiplier(&self) -> f32 {
-        let prism_multi = if self.perks[PERKS.prism_cube()] && self.shares > 10. {
-            self.shares.log10()
-        } else {
-            1.
-        };
-        let spiral_multi = if self.perks[PERKS.spiral()] {
-            1.1_f32.powi(self.generators)
-        } else {
-            1.
-        };
-        let dance_multi = if self.perks[PERKS.dance_robot_dance()] {
-            4.
-        } else {
-            1.
-        };
-        prism_multi * spiral_multi * dance_multi
+        self.perks()
+            .map(|(perk, level)| perk.effect.generator_multiplier(level, self))
+            .product()
+    }
+
+    /// Get the amount of shares a manual collection gives.
+    fn collection_amount(&self) -> f32 {
+        self.perks()
+            .filter_map(|(perk, level)| perk.effect.collection(level, self))
+            .fold(1., f32::max)
     }
 
     /// Whether or not shares can be collected right now.
@@ -87,9 +163,9 @@ impl Shares {
         }
     }
 
-    /// Whether or not a new generator can be bought right now.
-    fn can_buy_generator(&self) -> bool {
-        self.shares >= self.next_generator_cost()
+    /// Whether or not `count` new generators can be bought right now.
+    fn can_buy_generators(&self, count: i32) -> bool {
+        self.shares >= self.generators_cost(count)
     }
 
     /// Whether or not a prestige reset can be performed.
@@ -97,8 +173,8 @@ impl Shares {
         self.shares >= self.next_prestige_cost()
     }
 
-    /// Tick generators if enough time has passed.
-    async fn update(&mut self, postgres: &PgPool) -> Result<()> {
+    /// Tick generators if enough time has passed. `self` should be locked by `transaction`.
+    async fn update(&mut self, transaction: &mut Transaction<'_, Postgres>) -> Result<()> {
         let ticks =
             ((Utc::now() - self.generation_time) / Self::COLLECTION_COOLDOWN).num_seconds() as i32;
 
@@ -116,15 +192,113 @@ impl Shares {
                 self.generation_time,
                 self.user_id
             )
-            .execute(postgres)
+            .execute(&mut *transaction)
             .await?;
         }
 
         Ok(())
     }
+
+    /// Collect shares for user with `user_id` if they can.
+    /// Returns their `Shares` afterwards and whether they were collected.
+    async fn collect(user_id: i64, postgres: &PgPool) -> Result<(Self, bool)> {
+        let mut transaction = postgres.begin().await?;
+        let mut shares = Self::lock(user_id, &mut transaction).await?;
+        shares.update(&mut transaction).await?;
+
+        let collected = shares.can_collect()?;
+        if collected {
+            shares.collection_time = Some(Utc::now());
+            shares.shares += shares.collection_amount();
+            query!(
+                "UPDATE share
+                SET (shares, collection_time) = ($1, $2)
+                WHERE user_id = $3",
+                shares.shares,
+                shares.collection_time,
+                shares.user_id
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok((shares, collected))
+    }
+
+    /// Buy `count` generators for user with `user_id` if they can afford them, or as many as they can afford if `None`.
+    /// Returns their `Shares` afterwards, how many were bought and what they cost.
+    async fn buy_generators(
+        user_id: i64,
+        count: Option<i32>,
+        postgres: &PgPool,
+    ) -> Result<(Self, i32, f32)> {
+        let mut transaction = postgres.begin().await?;
+        let mut shares = Self::lock(user_id, &mut transaction).await?;
+        shares.update(&mut transaction).await?;
+
+        let count = count.unwrap_or_else(|| shares.affordable_generators());
+        let cost = shares.generators_cost(count);
+        let bought = count > 0 && shares.can_buy_generators(count);
+        if bought {
+            shares.shares -= cost;
+            shares.generators += count;
+            query!(
+                "UPDATE share
+                SET (shares, generators) = ($1, $2)
+                WHERE user_id = $3",
+                shares.shares,
+                shares.generators,
+                shares.user_id
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(match bought {
+            true => (shares, count, cost),
+            false => (shares, 0, 0.),
+        })
+    }
+
+    /// Perform a prestige reset for user with `user_id` if they can afford it.
+    /// Returns their `Shares` afterwards and whether they prestiged.
+    async fn prestige(user_id: i64, postgres: &PgPool) -> Result<(Self, bool)> {
+        let mut transaction = postgres.begin().await?;
+        let mut shares = Self::lock(user_id, &mut transaction).await?;
+        shares.update(&mut transaction).await?;
+
+        let prestiged = shares.can_prestige();
+        if prestiged {
+            shares.shares = 0.;
+            shares.generators = 0;
+            shares.collection_time = None;
+            shares.generation_time = Utc::now();
+            shares.prestige_count += 1;
+            shares.prestige_points += 1;
+            query!(
+                "UPDATE share
+                SET (shares, generators, collection_time, generation_time, prestige_count, prestige_points) = ($2, $3, $4, $5, $6, $7)
+                WHERE user_id = $1",
+                shares.user_id,
+                shares.shares,
+                shares.generators,
+                shares.collection_time,
+                shares.generation_time,
+                shares.prestige_count,
+                shares.prestige_points
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok((shares, prestiged))
+    }
 }
 
-#[poise::command(slash_command, subcommands("get", "leaderboard"))]
+#[poise::command(slash_command, subcommands("get", "leaderboard", "respec", "give"))]
 pub async fn shares(_ctx: Context<'_>) -> Result<()> {
     Ok(())
 }
@@ -135,24 +309,7 @@ pub async fn get(ctx: Context<'_>) -> Result<()> {
     let author_id: i64 = ctx.author().id.get().try_into()?;
     let postgres = ctx.data().postgres.clone();
 
-    let mut shares = match query_as!(Shares, "SELECT * FROM share WHERE user_id = $1", author_id)
-        .fetch_optional(&postgres)
-        .await?
-    // would LOVE to do this with .unwrap_or_else()
-    {
-        Some(shares) => shares,
-        None => {
-            query!(
-                "INSERT INTO share(user_id) VALUES($1)",
-                author_id,
-            )
-            .execute(&postgres)
-            .await?;
-            Shares::fetch_one(author_id, &postgres).await?
-        }
-    };
-
-    shares.update(&postgres).await?;
+    let shares = Shares::fetch_updated(author_id, &postgres).await?;
 
     ctx.send(
         CreateReply::new()
@@ -190,24 +347,36 @@ pub async fn get(ctx: Context<'_>) -> Result<()> {
                         true,
                     ),
             )
-            .components(vec![CreateActionRow::Buttons(vec![
-                CreateButton::new(COLLECT_BUTTON)
-                    .label("Collect Shares")
-                    .emoji('🩸')
-                    .disabled(!shares.can_collect()?),
-                CreateButton::new(BUY_GENERATOR_BUTTON)
-                    .label("Buy Generator")
-                    .emoji('🏭')
-                    .disabled(!shares.can_buy_generator()),
-                CreateButton::new(PRESTIGE_BUTTON)
-                    .label("Prestige")
-                    .emoji('🔄')
-                    .disabled(!shares.can_prestige()),
-                CreateButton::new("perk_shop:0")
-                    .label("Perk Shop")
-                    .emoji('➕')
-                    .disabled(shares.prestige_count < 1),
-            ])]),
+            .components(vec![
+                CreateActionRow::Buttons(vec![
+                    CreateButton::new(COLLECT_BUTTON)
+                        .label("Collect Shares")
+                        .emoji('🩸')
+                        .disabled(!shares.can_collect()?),
+                    CreateButton::new(PRESTIGE_BUTTON)
+                        .label("Prestige")
+                        .emoji('🔄')
+                        .disabled(!shares.can_prestige()),
+                    CreateButton::new("perk_shop:0")
+                        .label("Perk Shop")
+                        .emoji('➕')
+                        .disabled(shares.prestige_count < 1),
+                ]),
+                CreateActionRow::Buttons(vec![
+                    CreateButton::new(BUY_GENERATOR_BUTTON)
+                        .label("Buy Generator")
+                        .emoji('🏭')
+                        .disabled(!shares.can_buy_generators(1)),
+                    CreateButton::new(BUY_10_GENERATORS_BUTTON)
+                        .label("Buy x10")
+                        .emoji('🏭')
+                        .disabled(!shares.can_buy_generators(10)),
+                    CreateButton::new(BUY_MAX_GENERATORS_BUTTON)
+                        .label(format!("Buy Max ({})", shares.affordable_generators()))
+                        .emoji('🏭')
+                        .disabled(!shares.can_buy_generators(1)),
+                ]),
+            ]),
     )
     .await?;
 
@@ -221,13 +390,7 @@ pub async fn leaderboard(ctx: Context<'_>) -> Result<()> {
 
     ctx.defer().await?;
 
-    let mut shares_vec = query_as!(Shares, "SELECT * FROM share")
-        .fetch_all(&postgres)
-        .await?;
-
-    for shares in shares_vec.iter_mut() {
-        shares.update(&postgres).await?;
-    }
+    let mut shares_vec = Shares::fetch_all_updated(&postgres).await?;
     shares_vec.sort_unstable_by(|a, b| b.shares.partial_cmp(&a.shares).unwrap());
 
     let mut fields: Vec<(String, String, bool)> = Vec::new();
@@ -275,26 +438,10 @@ pub async fn on_collect(
 
     interaction.defer_ephemeral(&ctx.http).await?;
 
-    let mut shares = Shares::fetch_one(interaction.user.id.get().try_into()?, &postgres).await?;
-    shares.update(&postgres).await?;
+    let (shares, collected) =
+        Shares::collect(interaction.user.id.get().try_into()?, &postgres).await?;
 
-    if shares.can_collect()? {
-        shares.collection_time = Some(Utc::now());
-        shares.shares += if shares.perks[PERKS.electric_love()] && shares.generators > 0 {
-            10. * shares.generators as f32
-        } else {
-            1.
-        };
-        query!(
-            "UPDATE share
-                SET (shares, collection_time) = ($1, $2)
-                WHERE user_id = $3",
-            shares.shares,
-            shares.collection_time,
-            shares.user_id
-        )
-        .execute(&postgres)
-        .await?;
+    if collected {
         interaction
             .edit_response(
                 &ctx.http,
@@ -322,50 +469,57 @@ pub async fn on_collect(
     Ok(())
 }
 
+/// Buy `count` generators, or as many as can be afforded if `None`.
 pub async fn on_buy_generator(
     framework_ctx: FrameworkContext<'_>,
     ctx: &SerenityContext,
     interaction: &ComponentInteraction,
+    count: Option<i32>,
 ) -> Result<()> {
     let postgres = framework_ctx.user_data.postgres.clone();
 
     interaction.defer_ephemeral(&ctx.http).await?;
 
-    let mut shares = Shares::fetch_one(interaction.user.id.get().try_into()?, &postgres).await?;
-    shares.update(&postgres).await?;
+    let (shares, bought, cost) =
+        Shares::buy_generators(interaction.user.id.get().try_into()?, count, &postgres).await?;
 
-    let cost = shares.next_generator_cost();
-    if shares.shares >= cost {
-        shares.shares -= cost;
-        shares.generators += 1;
-        query!(
-            "UPDATE share
-                SET (shares, generators) = ($1, $2)
-                WHERE user_id = $3",
-            shares.shares,
-            shares.generators,
-            shares.user_id
-        )
-        .execute(&postgres)
-        .await?;
+    if bought > 0 {
         interaction
             .edit_response(
                 &ctx.http,
                 EditInteractionResponse::new().content(format!(
-                    "Generator purchased! You now have {}🩸 shares.",
+                    "{bought} {} purchased for {}🩸! You now have {}🏭 generators and {}🩸 shares.",
+                    if bought == 1 {
+                        "generator"
+                    } else {
+                        "generators"
+                    },
+                    num_format(cost)?,
+                    shares.generators,
                     num_format(shares.shares)?
                 )),
             )
             .await?;
     } else {
+        let count = count.unwrap_or(1);
         interaction
             .edit_response(
                 &ctx.http,
                 EditInteractionResponse::new().content(format!(
-                    "You cannot afford another generator right now. \
-                    You have {}🩸 shares and your next generator costs {}🩸.",
+                    "You cannot afford {} right now. \
+                    You have {}🩸 shares and {} {}🩸.",
+                    if count == 1 {
+                        "another generator".to_string()
+                    } else {
+                        format!("{count} more generators")
+                    },
                     num_format(shares.shares)?,
-                    num_format(cost)?
+                    if count == 1 {
+                        "your next generator costs"
+                    } else {
+                        "they cost"
+                    },
+                    num_format(shares.generators_cost(count))?
                 )),
             )
             .await?;
@@ -383,8 +537,7 @@ pub async fn on_prestige(
 
     interaction.defer_ephemeral(&ctx.http).await?;
 
-    let mut shares = Shares::fetch_one(interaction.user.id.get().try_into()?, &postgres).await?;
-    shares.update(&postgres).await?;
+    let shares = Shares::fetch_updated(interaction.user.id.get().try_into()?, &postgres).await?;
 
     let cost = shares.next_prestige_cost();
     if shares.shares >= cost {
@@ -434,31 +587,10 @@ pub async fn on_prestige_confirm(
 
     interaction.defer_ephemeral(&ctx.http).await?;
 
-    let mut shares = Shares::fetch_one(interaction.user.id.get().try_into()?, &postgres).await?;
-    shares.update(&postgres).await?;
+    let (shares, prestiged) =
+        Shares::prestige(interaction.user.id.get().try_into()?, &postgres).await?;
 
-    let cost = shares.next_prestige_cost();
-    if shares.shares >= cost {
-        shares.shares = 0.;
-        shares.generators = 0;
-        shares.collection_time = None;
-        shares.generation_time = Utc::now();
-        shares.prestige_count += 1;
-        shares.prestige_points += 1;
-        query!(
-            "UPDATE share
-                SET (shares, generators, collection_time, generation_time, prestige_count, prestige_points) = ($2, $3, $4, $5, $6, $7)
-                WHERE user_id = $1",
-            shares.user_id,
-            shares.shares,
-            shares.generators,
-            shares.collection_time,
-            shares.generation_time,
-            shares.prestige_count,
-            shares.prestige_points
-        )
-        .execute(&postgres)
-        .await?;
+    if prestiged {
         interaction
             .edit_response(
                 &ctx.http,
@@ -477,7 +609,7 @@ pub async fn on_prestige_confirm(
                     "You do not have enough 🩸shares to perform a prestige reset. \
                     You have {}🩸 shares and your next prestige costs {}🩸.",
                     num_format(shares.shares)?,
-                    cost
+                    shares.next_prestige_cost()
                 )),
             )
             .await?;
@@ -503,3 +635,111 @@ fn num_format(f: f32) -> Result<String> {
 
     Ok(num)
 }
+
+#[cfg(test)]
+mod fixtures {
+    use anyhow::Result;
+    use chrono::{Duration, Utc};
+    use sqlx::PgPool;
+
+    use super::Shares;
+
+    pub(super) const USER_ID: i64 = 1;
+
+    pub(super) fn shares(shares: f32, generators: i32, perks: &[(&str, i32)]) -> Shares {
+        Shares {
+            user_id: USER_ID,
+            shares,
+            generators,
+            prestige_points: 0,
+            prestige_count: 0,
+            collection_time: None,
+            generation_time: Utc::now(),
+            perk_ids: perks.iter().map(|(id, _)| id.to_string()).collect(),
+            perk_levels: perks.iter().map(|(_, level)| *level).collect(),
+            respec_time: None,
+            joined_at: Utc::now() - Duration::days(30),
+        }
+    }
+
+    /// Try `attempt` for `USER_ID` `times` times at once, returning how many times it worked.
+    pub(super) async fn race<F, Fut>(times: usize, postgres: &PgPool, attempt: F) -> Result<usize>
+    where
+        F: Fn(i64, PgPool) -> Fut,
+        Fut: std::future::Future<Output = Result<(Shares, bool)>> + Send + 'static,
+    {
+        let attempts: Vec<_> = (0..times)
+            .map(|_| tokio::spawn(attempt(USER_ID, postgres.clone())))
+            .collect();
+        let mut worked = 0;
+        for attempt in attempts {
+            if attempt.await??.1 {
+                worked += 1;
+            }
+        }
+        Ok(worked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use sqlx::{query, PgPool};
+
+    use super::fixtures::{race, shares, USER_ID};
+    use super::Shares;
+
+    #[test]
+    fn generator_costs() {
+        for perks in [&[][..], &[("world_is_mine", 1)]] {
+            let mut shares = shares(0., 3, perks);
+            let mut total = 0.;
+            for count in 1..=10 {
+                total += shares.generator_cost_ratio().powi(3 + count - 1);
+                let cost = shares.generators_cost(count);
+                assert!((cost - total).abs() <= total * 1e-5, "{cost} != {total}");
+
+                shares.shares = cost;
+                assert_eq!(shares.affordable_generators(), count);
+                shares.shares = cost * 0.999;
+                assert_eq!(shares.affordable_generators(), count - 1);
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn concurrent_collects(postgres: PgPool) -> Result<()> {
+        query!("INSERT INTO share(user_id) VALUES($1)", USER_ID)
+            .execute(&postgres)
+            .await?;
+
+        let collected = race(10, &postgres, |user_id, postgres| async move {
+            Shares::collect(user_id, &postgres).await
+        })
+        .await?;
+
+        assert_eq!(collected, 1);
+        assert_eq!(Shares::fetch_one(USER_ID, &postgres).await?.shares, 1.);
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn concurrent_generator_purchases(postgres: PgPool) -> Result<()> {
+        // exactly enough for 4 generators, at 1 + 2 + 4 + 8
+        query!("INSERT INTO share(user_id, shares) VALUES($1, 15)", USER_ID)
+            .execute(&postgres)
+            .await?;
+
+        let bought = race(10, &postgres, |user_id, postgres| async move {
+            let (shares, bought, _) = Shares::buy_generators(user_id, Some(1), &postgres).await?;
+            Ok((shares, bought > 0))
+        })
+        .await?;
+
+        let shares = Shares::fetch_one(USER_ID, &postgres).await?;
+        assert_eq!(bought, 4);
+        assert_eq!(shares.generators, 4);
+        assert_eq!(shares.shares, 0.);
+        Ok(())
+    }
+}
@@ -0,0 +1,383 @@
+use std::fmt::{Display, Formatter};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use poise::CreateReply;
+use serenity::all::{
+    ButtonStyle, ComponentInteraction, Context as SerenityContext, CreateActionRow, CreateButton,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    EditInteractionResponse, User,
+};
+use sqlx::{query, query_as, PgPool};
+
+use super::{num_format, Shares};
+use crate::{Context, FrameworkContext};
+
+/// Fraction of a gift the giver pays on top as a fee.
+const TRANSFER_FEE: f32 = 0.05;
+/// The amount of time (in seconds) someone has to have been playing before they can give or be given shares.
+const MIN_TRANSFER_AGE: i64 = 7 * 24 * 60 * 60;
+
+/// Why shares couldn't be given.
+pub enum TransferRefusal {
+    ToSelf,
+    NotPlaying,
+    AlreadyGiven,
+    SenderTooNew(DateTime<Utc>),
+    RecipientTooNew(DateTime<Utc>),
+    CantAfford { cost: f32, shares: f32 },
+}
+
+impl Display for TransferRefusal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ToSelf => write!(f, "You cannot give shares to yourself."),
+            Self::NotPlaying => write!(f, "They haven't started collecting shares yet."),
+            Self::AlreadyGiven => write!(f, "You already gave those shares."),
+            Self::SenderTooNew(time) => write!(
+                f,
+                "You haven't been collecting shares long enough to give them away. \
+                You can give shares <t:{}:R>.",
+                time.timestamp()
+            ),
+            Self::RecipientTooNew(time) => write!(
+                f,
+                "They haven't been collecting shares long enough to be given any. \
+                They can be given shares <t:{}:R>.",
+                time.timestamp()
+            ),
+            Self::CantAfford { cost, shares } => write!(
+                f,
+                "You cannot afford that right now. \
+                You have {}🩸 shares and it costs {}🩸 including the fee.",
+                num_format(*shares).unwrap_or_else(|_| shares.to_string()),
+                num_format(*cost).unwrap_or_else(|_| cost.to_string())
+            ),
+        }
+    }
+}
+
+/// The fee for giving `amount` shares.
+pub(super) fn transfer_fee(amount: f32) -> f32 {
+    amount * TRANSFER_FEE
+}
+
+impl Shares {
+    /// When this user has been playing long enough to give or be given shares, if they haven't yet.
+    fn transfer_time(&self) -> Option<DateTime<Utc>> {
+        Some(self.joined_at + Duration::seconds(MIN_TRANSFER_AGE)).filter(|time| *time > Utc::now())
+    }
+
+    /// Why this user can't give `amount` shares to `recipient`, if they can't.
+    pub(super) fn transfer_refusal(
+        &self,
+        recipient: Option<&Self>,
+        amount: f32,
+    ) -> Option<TransferRefusal> {
+        let Some(recipient) = recipient else {
+            return Some(TransferRefusal::NotPlaying);
+        };
+        let cost = amount + transfer_fee(amount);
+        if self.user_id == recipient.user_id {
+            Some(TransferRefusal::ToSelf)
+        } else if let Some(time) = self.transfer_time() {
+            Some(TransferRefusal::SenderTooNew(time))
+        } else if let Some(time) = recipient.transfer_time() {
+            Some(TransferRefusal::RecipientTooNew(time))
+        } else if self.shares < cost {
+            Some(TransferRefusal::CantAfford {
+                cost,
+                shares: self.shares,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Give `amount` shares from user with `sender_id` to user with `recipient_id` if they can, and record it in the ledger.
+    /// A transfer with the same `nonce` as an earlier one is refused, so confirming twice only gives once.
+    /// Returns the sender's `Shares` afterwards and why it wasn't given, if it wasn't.
+    pub(super) async fn transfer(
+        sender_id: i64,
+        recipient_id: i64,
+        amount: f32,
+        nonce: i64,
+        guild_id: Option<i64>,
+        postgres: &PgPool,
+    ) -> Result<(Self, Option<TransferRefusal>)> {
+        let mut transaction = postgres.begin().await?;
+        query!(
+            "INSERT INTO share(user_id) VALUES($1) ON CONFLICT DO NOTHING",
+            sender_id
+        )
+        .execute(&mut transaction)
+        .await?;
+        // always locking in the same order means two transfers between the same users can't deadlock
+        let mut pair = query_as!(
+            Self,
+            "SELECT * FROM share WHERE user_id = ANY($1) ORDER BY user_id FOR UPDATE",
+            &[sender_id, recipient_id][..]
+        )
+        .fetch_all(&mut transaction)
+        .await?;
+        for shares in pair.iter_mut() {
+            shares.update(&mut transaction).await?;
+        }
+        let sender_index = pair
+            .iter()
+            .position(|shares| shares.user_id == sender_id)
+            .ok_or_else(|| anyhow!("sender {sender_id} has no shares"))?;
+        let mut sender = pair.remove(sender_index);
+        let mut recipient = pair.pop();
+
+        // giving to yourself only locks one row
+        let refusal = match recipient {
+            None if sender_id == recipient_id => Some(TransferRefusal::ToSelf),
+            _ => sender.transfer_refusal(recipient.as_ref(), amount),
+        };
+        if let (None, Some(recipient)) = (&refusal, &mut recipient) {
+            let fee = transfer_fee(amount);
+            let recorded = query!(
+                "INSERT INTO share_transfer(sender_id, recipient_id, amount, fee, nonce, guild_id)
+                VALUES($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (nonce) DO NOTHING",
+                sender.user_id,
+                recipient.user_id,
+                amount,
+                fee,
+                nonce,
+                guild_id
+            )
+            .execute(&mut transaction)
+            .await?
+            .rows_affected();
+            if recorded == 0 {
+                return Ok((sender, Some(TransferRefusal::AlreadyGiven)));
+            }
+
+            sender.shares -= amount + fee;
+            recipient.shares += amount;
+            query!(
+                "UPDATE share SET shares = $1 WHERE user_id = $2",
+                sender.shares,
+                sender.user_id
+            )
+            .execute(&mut transaction)
+            .await?;
+            query!(
+                "UPDATE share SET shares = $1 WHERE user_id = $2",
+                recipient.shares,
+                recipient.user_id
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok((sender, refusal))
+    }
+}
+
+/// Give some of your shares to someone else, for a fee
+#[poise::command(slash_command)]
+pub async fn give(
+    ctx: Context<'_>,
+    #[description = "Who to give shares to"] user: User,
+    #[description = "How many shares to give"]
+    #[min = 1]
+    amount: f32,
+) -> Result<()> {
+    let postgres = &ctx.data().postgres;
+    let sender = Shares::fetch_updated(ctx.author().id.get().try_into()?, postgres).await?;
+    let recipient = query_as!(
+        Shares,
+        "SELECT * FROM share WHERE user_id = $1",
+        i64::try_from(user.id.get())?
+    )
+    .fetch_optional(postgres)
+    .await?;
+
+    let reply = match sender.transfer_refusal(recipient.as_ref(), amount) {
+        Some(refusal) => CreateReply::new().content(refusal.to_string()),
+        None => CreateReply::new()
+            .content(format!(
+                "Are you sure you want to give {}🩸 shares to {}?\n\
+                It will cost you {}🩸 shares, including a {}🩸 fee.",
+                num_format(amount)?,
+                user.name,
+                num_format(amount + transfer_fee(amount))?,
+                num_format(transfer_fee(amount))?
+            ))
+            .components(vec![CreateActionRow::Buttons(vec![CreateButton::new(
+                // the command's id makes each confirmation button give at most once
+                format!("give_confirm:{}:{amount}:{}", user.id, ctx.id()),
+            )
+            .emoji('✔')
+            .style(ButtonStyle::Success)])]),
+    };
+    ctx.send(reply.ephemeral(true)).await?;
+
+    Ok(())
+}
+
+pub async fn on_give_confirm(
+    framework_ctx: FrameworkContext<'_>,
+    ctx: &SerenityContext,
+    interaction: &ComponentInteraction,
+    recipient_id: i64,
+    amount: f32,
+    nonce: i64,
+) -> Result<()> {
+    let postgres = framework_ctx.user_data.postgres.clone();
+    let sender_id: i64 = interaction.user.id.get().try_into()?;
+    let guild_id = interaction
+        .guild_id
+        .map(|id| i64::try_from(id.get()))
+        .transpose()?;
+
+    // take the button away straight away so it can't be clicked again
+    interaction
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .content("Giving shares...")
+                    .components(Vec::new()),
+            ),
+        )
+        .await?;
+
+    let (sender, refusal) =
+        Shares::transfer(sender_id, recipient_id, amount, nonce, guild_id, &postgres).await?;
+
+    if let Some(refusal) = refusal {
+        interaction
+            .edit_response(
+                &ctx.http,
+                EditInteractionResponse::new().content(refusal.to_string()),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    interaction
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new().content(format!(
+                "Shares given! You now have {}🩸 shares.",
+                num_format(sender.shares)?
+            )),
+        )
+        .await?;
+    interaction
+        .channel_id
+        .send_message(
+            &ctx,
+            CreateMessage::new().content(format!(
+                "<@{sender_id}> gave <@{recipient_id}> {}🩸 shares!",
+                num_format(amount)?
+            )),
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    use anyhow::Result;
+    use chrono::Utc;
+    use sqlx::{query, PgPool};
+
+    use super::{Shares, TransferRefusal};
+    use crate::commands::shares::fixtures::{race, shares, USER_ID};
+
+    #[test]
+    fn transfer_refusals() {
+        let sender = shares(21., 0, &[]);
+        let mut recipient = shares(0., 0, &[]);
+        recipient.user_id = USER_ID + 1;
+
+        assert!(sender.transfer_refusal(Some(&recipient), 20.).is_none());
+        assert!(matches!(
+            sender.transfer_refusal(Some(&recipient), 21.),
+            Some(TransferRefusal::CantAfford { .. })
+        ));
+        assert!(matches!(
+            sender.transfer_refusal(Some(&sender), 1.),
+            Some(TransferRefusal::ToSelf)
+        ));
+        assert!(matches!(
+            sender.transfer_refusal(None, 1.),
+            Some(TransferRefusal::NotPlaying)
+        ));
+
+        recipient.joined_at = Utc::now();
+        assert!(matches!(
+            sender.transfer_refusal(Some(&recipient), 1.),
+            Some(TransferRefusal::RecipientTooNew(_))
+        ));
+    }
+
+    #[sqlx::test]
+    async fn concurrent_transfers(postgres: PgPool) -> Result<()> {
+        let recipient_id = USER_ID + 1;
+        // exactly enough for 10 gifts of 10, with a 0.5 fee each
+        query!(
+            "INSERT INTO share(user_id, shares, joined_at) VALUES($1, 105, 'epoch'), ($2, 0, 'epoch')",
+            USER_ID,
+            recipient_id
+        )
+        .execute(&postgres)
+        .await?;
+
+        let nonces = AtomicI64::new(0);
+        let given = race(15, &postgres, move |user_id, postgres| {
+            let nonce = nonces.fetch_add(1, Ordering::Relaxed);
+            async move {
+                let (shares, refusal) =
+                    Shares::transfer(user_id, recipient_id, 10., nonce, None, &postgres).await?;
+                Ok((shares, refusal.is_none()))
+            }
+        })
+        .await?;
+
+        let transfers = query!(r#"SELECT COUNT(*) AS "count!" FROM share_transfer"#)
+            .fetch_one(&postgres)
+            .await?;
+        assert_eq!(given, 10);
+        assert_eq!(transfers.count, 10);
+        assert_eq!(Shares::fetch_one(USER_ID, &postgres).await?.shares, 0.);
+        assert_eq!(
+            Shares::fetch_one(recipient_id, &postgres).await?.shares,
+            100.
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn repeated_confirmations(postgres: PgPool) -> Result<()> {
+        let recipient_id = USER_ID + 1;
+        query!(
+            "INSERT INTO share(user_id, shares, joined_at) VALUES($1, 105, 'epoch'), ($2, 0, 'epoch')",
+            USER_ID,
+            recipient_id
+        )
+        .execute(&postgres)
+        .await?;
+
+        // every click on the same confirmation button has the same nonce
+        let given = race(5, &postgres, move |user_id, postgres| async move {
+            let (shares, refusal) =
+                Shares::transfer(user_id, recipient_id, 10., 1, None, &postgres).await?;
+            Ok((shares, refusal.is_none()))
+        })
+        .await?;
+
+        assert_eq!(given, 1);
+        assert_eq!(Shares::fetch_one(USER_ID, &postgres).await?.shares, 94.5);
+        Ok(())
+    }
+}
@@ -1,66 +1,342 @@
-use anyhow::Result;
+use std::fmt::{Display, Formatter};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use poise::CreateReply;
 use serenity::all::{
     ButtonStyle, ComponentInteraction, CreateEmbed, CreateEmbedFooter, EditInteractionResponse,
 };
 use serenity::all::{Context as SerenityContext, CreateActionRow, CreateButton};
-use sqlx::query;
+use sqlx::{query, PgPool};
 
 use super::Shares;
-use crate::FrameworkContext;
+use crate::{Context, FrameworkContext};
+
+pub const RESPEC_CONFIRM_BUTTON: &str = "respec_confirm";
 
-pub struct Perk<'a> {
-    name: &'a str,
-    description: &'a str,
+/// What a perk does at each of its levels.
+pub enum Effect {
+    /// Manual collection gives this many 🩸shares per 🏭generator per level.
+    CollectPerGenerator(f32),
+    /// 🏭Generators are boosted by (log10 of 🩸shares) per level.
+    LogSharesMultiplier,
+    /// Each 🏭generator boosts 🏭generators by this fraction per level.
+    GeneratorCompounding(f32),
+    /// 🏭Generators are this many times stronger per level.
+    GeneratorMultiplier(f32),
+    /// Each 🏭generator costs this many times more than the last, instead of 2.
+    GeneratorCostRatio(f32),
 }
 
-impl<'a> Perk<'a> {
-    const fn new(name: &'a str, description: &'a str) -> Self {
-        Self { name, description }
+impl Effect {
+    /// How much this multiplies generator production by at `level`.
+    pub(super) fn generator_multiplier(&self, level: i32, shares: &Shares) -> f32 {
+        match self {
+            Self::LogSharesMultiplier if shares.shares > 10. => shares.shares.log10().powi(level),
+            Self::GeneratorCompounding(boost) => {
+                (1. + boost * level as f32).powi(shares.generators)
+            }
+            Self::GeneratorMultiplier(multiplier) => multiplier.powi(level),
+            _ => 1.,
+        }
+    }
+
+    /// How many shares a manual collection gives at `level`, if this changes it.
+    pub(super) fn collection(&self, level: i32, shares: &Shares) -> Option<f32> {
+        match self {
+            Self::CollectPerGenerator(per_generator) if shares.generators > 0 => {
+                Some(per_generator * level as f32 * shares.generators as f32)
+            }
+            _ => None,
+        }
+    }
+
+    /// What each generator costs relative to the last, if this changes it.
+    pub(super) fn generator_cost_ratio(&self) -> Option<f32> {
+        match self {
+            Self::GeneratorCostRatio(ratio) => Some(*ratio),
+            _ => None,
+        }
     }
 }
 
-pub trait FromName {
-    fn electric_love(&self) -> usize;
-    fn prism_cube(&self) -> usize;
-    fn spiral(&self) -> usize;
-    fn dance_robot_dance(&self) -> usize;
-    fn world_is_mine(&self) -> usize;
+/// Something that can be bought with prestige points to change how the game plays.
+pub struct Perk {
+    /// What the perk is saved as, so it must never change.
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Prestige points the first level costs, with each level after costing that much more.
+    pub cost: i32,
+    /// Ids of perks that must be bought first.
+    pub prerequisites: &'static [&'static str],
+    pub max_level: i32,
+    pub effect: Effect,
 }
 
-impl FromName for [Perk<'_>] {
-    fn electric_love(&self) -> usize {
-        0
+impl Perk {
+    /// Return the perk with `id`, if there is one.
+    pub fn from_id(id: &str) -> Option<&'static Self> {
+        PERKS.iter().find(|perk| perk.id == id)
+    }
+
+    /// Prestige points it costs to reach `level`.
+    pub fn cost(&self, level: i32) -> i32 {
+        self.cost * level
+    }
+
+    /// Prestige points it costs to reach `level` from nothing.
+    pub fn total_cost(&self, level: i32) -> i32 {
+        self.cost * level * (level + 1) / 2
+    }
+}
+
+pub static PERKS: [Perk; 5] = [
+    Perk {
+        id: "electric_love",
+        name: "Electric Love",
+        description: "Manual collection now gives (10 * 🏭generators) 🩸shares.",
+        cost: 1,
+        prerequisites: &[],
+        max_level: 1,
+        effect: Effect::CollectPerGenerator(10.),
+    },
+    Perk {
+        id: "prism_cube",
+        name: "Prism Cube",
+        description: "🏭Generators are boosted by (log10 of 🩸shares).",
+        cost: 1,
+        prerequisites: &[],
+        max_level: 1,
+        effect: Effect::LogSharesMultiplier,
+    },
+    Perk {
+        id: "spiral",
+        name: "Spiral",
+        description: "🏭Generators boost 🏭generators by 10% each.",
+        cost: 1,
+        prerequisites: &[],
+        max_level: 1,
+        effect: Effect::GeneratorCompounding(0.1),
+    },
+    Perk {
+        id: "dance_robot_dance",
+        name: "Dance Robot Dance",
+        description: "🏭Generators are 4x stronger.",
+        cost: 1,
+        prerequisites: &[],
+        max_level: 1,
+        effect: Effect::GeneratorMultiplier(4.),
+    },
+    Perk {
+        id: "world_is_mine",
+        name: "World is Mine",
+        description: "🏭Generators now cost (1.6 ^ 🏭generators) instead.",
+        cost: 1,
+        prerequisites: &[],
+        max_level: 1,
+        effect: Effect::GeneratorCostRatio(1.6),
+    },
+];
+
+/// Why a perk couldn't be bought.
+pub enum PerkRefusal {
+    MaxLevel(&'static Perk),
+    MissingPrerequisite(&'static Perk),
+    CantAfford { cost: i32, points: i32 },
+}
+
+impl Display for PerkRefusal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MaxLevel(perk) => {
+                write!(f, "You already have {} at its highest level.", perk.name)
+            }
+            Self::MissingPrerequisite(perk) => write!(f, "You need {} first.", perk.name),
+            Self::CantAfford { cost, points } => write!(
+                f,
+                "You cannot afford that perk right now. \
+                You have {points} prestige points and it costs {cost}."
+            ),
+        }
+    }
+}
+
+impl Shares {
+    /// The level this user has the perk with `id` at, or 0 if they don't have it.
+    pub(super) fn perk_level(&self, id: &str) -> i32 {
+        self.perk_ids
+            .iter()
+            .position(|perk_id| perk_id == id)
+            .and_then(|index| self.perk_levels.get(index).copied())
+            .unwrap_or(0)
+    }
+
+    /// Every perk this user has, with its level.
+    pub(super) fn perks(&self) -> impl Iterator<Item = (&'static Perk, i32)> + '_ {
+        PERKS
+            .iter()
+            .map(|perk| (perk, self.perk_level(perk.id)))
+            .filter(|(_, level)| *level > 0)
     }
-    fn prism_cube(&self) -> usize {
-        1
+
+    /// Prestige points a respec would give back.
+    pub(super) fn perk_refund(&self) -> i32 {
+        self.perks()
+            .map(|(perk, level)| perk.total_cost(level))
+            .sum()
     }
-    fn spiral(&self) -> usize {
-        2
+
+    /// When a respec can next be done, if not right now.
+    pub(super) fn next_respec_time(&self) -> Option<DateTime<Utc>> {
+        self.respec_time
+            .map(|time| time + Duration::seconds(Self::RESPEC_COOLDOWN))
+            .filter(|time| *time > Utc::now())
     }
-    fn dance_robot_dance(&self) -> usize {
-        3
+
+    /// Why the next level of `perk` can't be bought right now, if it can't.
+    fn perk_refusal(&self, perk: &'static Perk) -> Option<PerkRefusal> {
+        let level = self.perk_level(perk.id);
+        if level >= perk.max_level {
+            return Some(PerkRefusal::MaxLevel(perk));
+        }
+        if let Some(prerequisite) = perk
+            .prerequisites
+            .iter()
+            .filter_map(|id| Perk::from_id(id))
+            .find(|prerequisite| self.perk_level(prerequisite.id) < 1)
+        {
+            return Some(PerkRefusal::MissingPrerequisite(prerequisite));
+        }
+        let cost = perk.cost(level + 1);
+        (self.prestige_points < cost).then_some(PerkRefusal::CantAfford {
+            cost,
+            points: self.prestige_points,
+        })
     }
-    fn world_is_mine(&self) -> usize {
-        4
+
+    /// Buy the next level of `perk` for user with `user_id` if they can.
+    /// Returns their `Shares` afterwards and why it wasn't bought, if it wasn't.
+    async fn buy_perk(
+        user_id: i64,
+        perk: &'static Perk,
+        postgres: &PgPool,
+    ) -> Result<(Self, Option<PerkRefusal>)> {
+        let mut transaction = postgres.begin().await?;
+        let mut shares = Self::lock(user_id, &mut transaction).await?;
+
+        let refusal = shares.perk_refusal(perk);
+        if refusal.is_none() {
+            let level = shares.perk_level(perk.id) + 1;
+            shares.prestige_points -= perk.cost(level);
+            match shares.perk_ids.iter().position(|id| id == perk.id) {
+                Some(index) => shares.perk_levels[index] = level,
+                None => {
+                    shares.perk_ids.push(perk.id.to_string());
+                    shares.perk_levels.push(level);
+                }
+            }
+            query!(
+                "UPDATE share
+                SET (prestige_points, perk_ids, perk_levels) = ($1, $2, $3)
+                WHERE user_id = $4",
+                shares.prestige_points,
+                &shares.perk_ids,
+                &shares.perk_levels,
+                shares.user_id
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok((shares, refusal))
+    }
+
+    /// The amount of time (in seconds) until perks can be respecced again.
+    const RESPEC_COOLDOWN: i64 = 24 * 60 * 60;
+
+    /// Remove every perk from user with `user_id` and refund what they cost, if they have any and it's been long enough since the last respec.
+    /// Returns their `Shares` afterwards and how many prestige points were refunded, if they respecced.
+    async fn respec(user_id: i64, postgres: &PgPool) -> Result<(Self, Option<i32>)> {
+        let mut transaction = postgres.begin().await?;
+        let mut shares = Self::lock(user_id, &mut transaction).await?;
+
+        let refund = shares.perk_refund();
+        let respecced = refund > 0 && shares.next_respec_time().is_none();
+        if respecced {
+            shares.prestige_points += refund;
+            shares.perk_ids.clear();
+            shares.perk_levels.clear();
+            shares.respec_time = Some(Utc::now());
+            query!(
+                "UPDATE share
+                SET (prestige_points, perk_ids, perk_levels, respec_time) = ($1, $2, $3, $4)
+                WHERE user_id = $5",
+                shares.prestige_points,
+                &shares.perk_ids,
+                &shares.perk_levels,
+                shares.respec_time,
+                shares.user_id
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok((shares, respecced.then_some(refund)))
+    }
+
+    /// Why a respec can't be done right now, if it can't.
+    fn respec_refusal(&self) -> Option<String> {
+        if self.perk_refund() == 0 {
+            Some("You don't have any perks to respec.".to_string())
+        } else {
+            self.next_respec_time().map(|time| {
+                format!(
+                    "You cannot respec right now. You can respec <t:{}:R>.",
+                    time.timestamp()
+                )
+            })
+        }
     }
 }
 
-pub const PERKS: [Perk; 5] = [
-    Perk::new(
-        "Electric Love",
-        "Manual collection now gives (10 * 🏭generators) 🩸shares.",
-    ),
-    Perk::new(
-        "Prism Cube",
-        "🏭Generators are boosted by (log10 of 🩸shares).",
-    ),
-    Perk::new("Spiral", "🏭Generators boost 🏭generators by 10% each."),
-    Perk::new("Dance Robot Dance", "🏭Generators are 4x stronger."),
-    Perk::new(
-        "World is Mine",
-        "🏭Generators now cost (1.6 ^ 🏭generators) instead.",
-    ),
-];
+/// Refund all your perks to pick new ones
+#[poise::command(slash_command)]
+pub async fn respec(ctx: Context<'_>) -> Result<()> {
+    let shares =
+        Shares::fetch_updated(ctx.author().id.get().try_into()?, &ctx.data().postgres).await?;
+
+    let reply = match shares.respec_refusal() {
+        Some(refusal) => CreateReply::new().content(refusal),
+        None => CreateReply::new()
+            .content(format!(
+                "Are you sure you want to respec?\n\
+                You will lose {}.\n\
+                You will get back {} prestige points, making your total {}.\n\
+                You won't be able to respec again for a day.",
+                shares
+                    .perks()
+                    .map(|(perk, level)| match perk.max_level {
+                        1 => perk.name.to_string(),
+                        _ => format!("{} level {level}", perk.name),
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                shares.perk_refund(),
+                shares.prestige_points + shares.perk_refund()
+            ))
+            .components(vec![CreateActionRow::Buttons(vec![CreateButton::new(
+                RESPEC_CONFIRM_BUTTON,
+            )
+            .emoji('✔')
+            .style(ButtonStyle::Success)])]),
+    };
+    ctx.send(reply.ephemeral(true)).await?;
+
+    Ok(())
+}
 
 pub async fn on_perk_shop(
     framework_ctx: FrameworkContext<'_>,
@@ -73,6 +349,24 @@ pub async fn on_perk_shop(
     interaction.defer_ephemeral(&ctx.http).await?;
 
     let shares = Shares::fetch_one(interaction.user.id.get().try_into()?, &postgres).await?;
+    let perk = &PERKS[perk_index];
+    let level = shares.perk_level(perk.id);
+
+    let mut description = perk.description.to_string();
+    if level < perk.max_level {
+        description += &format!("\n\nCosts {} prestige points.", perk.cost(level + 1));
+    }
+    if !perk.prerequisites.is_empty() {
+        description += &format!(
+            "\nRequires {}.",
+            perk.prerequisites
+                .iter()
+                .filter_map(|id| Perk::from_id(id))
+                .map(|prerequisite| prerequisite.name)
+                .collect::<Vec<&str>>()
+                .join(", ")
+        );
+    }
 
     interaction
         .edit_response(
@@ -84,12 +378,14 @@ pub async fn on_perk_shop(
                             "You have {} prestige points.",
                             shares.prestige_points
                         )))
-                        .title(if shares.perks[perk_index] {
-                            PERKS[perk_index].name.to_string() + " (purchased)"
-                        } else {
-                            PERKS[perk_index].name.to_string()
+                        .title(match (level, perk.max_level) {
+                            (0, _) => perk.name.to_string(),
+                            (_, 1) => format!("{} (purchased)", perk.name),
+                            (level, max_level) => {
+                                format!("{} (level {level}/{max_level})", perk.name)
+                            }
                         })
-                        .description(PERKS[perk_index].description),
+                        .description(description),
                 )
                 .components(vec![CreateActionRow::Buttons(vec![
                     CreateButton::new(format!(
@@ -97,10 +393,10 @@ pub async fn on_perk_shop(
                         (perk_index as isize - 1).rem_euclid(PERKS.len() as isize)
                     ))
                     .emoji('⬅'),
-                    CreateButton::new(format!("buy_perk:{}", perk_index))
+                    CreateButton::new(format!("buy_perk:{}", perk.id))
                         .emoji('🛒')
                         .style(ButtonStyle::Secondary)
-                        .disabled(shares.perks[perk_index] || shares.prestige_points < 1),
+                        .disabled(shares.perk_refusal(perk).is_some()),
                     CreateButton::new(format!("perk_shop:{}", (perk_index + 1) % PERKS.len()))
                         .emoji('➡'),
                 ])]),
@@ -114,48 +410,133 @@ pub async fn on_buy_perk(
     framework_ctx: FrameworkContext<'_>,
     ctx: &SerenityContext,
     interaction: &ComponentInteraction,
-    perk_index: usize,
+    perk_id: &str,
 ) -> Result<()> {
     let postgres = framework_ctx.user_data.postgres.clone();
+    let perk = Perk::from_id(perk_id).ok_or_else(|| anyhow!("no perk with id {perk_id}"))?;
 
     interaction.defer_ephemeral(&ctx.http).await?;
 
-    let mut shares = Shares::fetch_one(interaction.user.id.get().try_into()?, &postgres).await?;
-
-    if shares.prestige_points > 0 {
-        shares.prestige_points -= 1;
-        shares.perks.set(perk_index, true);
-        query!(
-            "UPDATE share
-                SET (prestige_points, perks) = ($1, $2)
-                WHERE user_id = $3",
-            shares.prestige_points,
-            shares.perks,
-            shares.user_id
-        )
-        .execute(&postgres)
+    let (shares, refusal) =
+        Shares::buy_perk(interaction.user.id.get().try_into()?, perk, &postgres).await?;
+
+    let content = match refusal {
+        None => format!(
+            "Perk {} purchased! You now have {} prestige points.",
+            match perk.max_level {
+                1 => perk.name.to_string(),
+                _ => format!("{} level {}", perk.name, shares.perk_level(perk.id)),
+            },
+            shares.prestige_points
+        ),
+        Some(refusal) => refusal.to_string(),
+    };
+    interaction
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
         .await?;
-        interaction
-            .edit_response(
-                &ctx.http,
-                EditInteractionResponse::new().content(format!(
-                    "Perk {} purchased! You now have {} prestige points.",
-                    PERKS[perk_index].name, shares.prestige_points
-                )),
-            )
-            .await?;
-    } else {
-        interaction
-            .edit_response(
-                &ctx.http,
-                EditInteractionResponse::new().content(format!(
-                    "You cannot afford to buy a perk right now. \
-                    You have {} prestige points and perks cost 1.",
-                    shares.prestige_points
-                )),
-            )
-            .await?;
-    }
 
     Ok(())
 }
+
+pub async fn on_respec_confirm(
+    framework_ctx: FrameworkContext<'_>,
+    ctx: &SerenityContext,
+    interaction: &ComponentInteraction,
+) -> Result<()> {
+    let postgres = framework_ctx.user_data.postgres.clone();
+
+    interaction.defer_ephemeral(&ctx.http).await?;
+
+    let (shares, refund) = Shares::respec(interaction.user.id.get().try_into()?, &postgres).await?;
+
+    let content = match refund {
+        Some(refund) => format!(
+            "Your perks are gone and {refund} prestige points were refunded. \
+            You now have {} prestige points.",
+            shares.prestige_points
+        ),
+        None => shares
+            .respec_refusal()
+            .ok_or_else(|| anyhow!("respec failed with no reason"))?,
+    };
+    interaction
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{Perk, PERKS};
+    use crate::commands::shares::fixtures::shares;
+
+    #[test]
+    fn perks_are_consistent() {
+        for (index, perk) in PERKS.iter().enumerate() {
+            assert!(
+                PERKS[..index].iter().all(|other| other.id != perk.id),
+                "{} is defined twice",
+                perk.id
+            );
+            assert!(
+                perk.max_level >= 1 && perk.cost >= 1,
+                "{} can't be bought",
+                perk.id
+            );
+            if let Some(ratio) = perk.effect.generator_cost_ratio() {
+                assert!(
+                    ratio > 1.,
+                    "{} makes generators stop getting more expensive",
+                    perk.id
+                );
+            }
+            for prerequisite in perk.prerequisites {
+                assert!(
+                    Perk::from_id(prerequisite).is_some() && *prerequisite != perk.id,
+                    "{} requires unknown perk {prerequisite}",
+                    perk.id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn perk_effects() {
+        let plain = shares(100., 5, &[]);
+        assert_eq!(plain.generator_multiplier(), 1.);
+        assert_eq!(plain.collection_amount(), 1.);
+
+        let boosted = shares(
+            100.,
+            5,
+            &[
+                ("prism_cube", 1),
+                ("dance_robot_dance", 1),
+                ("electric_love", 1),
+            ],
+        );
+        assert_eq!(boosted.generator_multiplier(), 8.);
+        assert_eq!(boosted.collection_amount(), 50.);
+        assert_eq!(
+            shares(100., 0, &[("electric_love", 1)]).collection_amount(),
+            1.
+        );
+    }
+
+    #[test]
+    fn perk_refunds() {
+        assert_eq!(shares(0., 0, &[]).perk_refund(), 0);
+        assert_eq!(
+            shares(0., 0, &[("spiral", 1), ("world_is_mine", 1)]).perk_refund(),
+            2
+        );
+
+        let mut shares = shares(0., 0, &[("spiral", 1)]);
+        assert_eq!(shares.next_respec_time(), None);
+        shares.respec_time = Some(Utc::now());
+        assert!(shares.next_respec_time().is_some());
+    }
+}
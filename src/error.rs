@@ -1,22 +1,29 @@
 use anyhow::{bail, Error, Result};
 use log::{debug, error};
-use poise::FrameworkError;
+use poise::{CreateReply, FrameworkError};
+use serenity::all::CreateAllowedMentions;
 
 use crate::commands::gelbooru::GelbooruError;
+use crate::commands::roll::RollError;
 use crate::DataWrapper;
 
 pub async fn handle_error(e: FrameworkError<'_, DataWrapper, Error>) -> Result<()> {
     match e {
         FrameworkError::Command { ctx, error } => {
             if ctx.framework().options.commands.contains(ctx.command()) {
-                let response = match error.downcast::<GelbooruError>() {
-                    Ok(e) => e.to_string(),
-                    Err(e) => {
-                        debug!("{:?}", e);
-                        "An error occurred while executing this command.".to_string()
-                    }
+                let response = if let Some(e) = error.downcast_ref::<GelbooruError>() {
+                    e.to_string()
+                } else if let Some(e) = error.downcast_ref::<RollError>() {
+                    e.to_string()
+                } else {
+                    debug!("{:?}", error);
+                    "An error occurred while executing this command.".to_string()
                 };
-                if let Err(e) = ctx.say(response).await {
+                // errors can echo what the user typed, so they shouldn't ping anyone
+                let reply = CreateReply::new()
+                    .content(response)
+                    .allowed_mentions(CreateAllowedMentions::new());
+                if let Err(e) = ctx.send(reply).await {
                     error!("could not reply in handle_error: {e}");
                 };
             }
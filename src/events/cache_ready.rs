@@ -3,13 +3,19 @@ use std::time::Duration;
 
 use anyhow::Result;
 use log::error;
-use serenity::all::CreateMessage;
+use serenity::all::{CreateAllowedMentions, CreateMessage};
 use serenity::client::Context;
 use serenity::futures::StreamExt;
-use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::id::GuildId;
 
+use crate::commands::config::GuildConfig;
+use crate::markov::sanitize::sanitize;
+use crate::markov::Bounds;
 use crate::FrameworkContext;
 
+/// How often a Markov chain that has learned something is written back to disk.
+const MARKOV_SAVE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 pub async fn handle(
     framework_ctx: FrameworkContext<'_>,
     ctx: &Context,
@@ -19,32 +25,60 @@ pub async fn handle(
     let cache = ctx.cache.clone();
     let http = ctx.http.clone();
     let markov = data.markov.clone();
+    let rng = data.rng.clone();
+    let postgres = data.postgres.clone();
 
     if !data.markov_loop_running.load(Ordering::Relaxed) {
+        let save_markov = markov.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(MARKOV_SAVE_INTERVAL).await;
+                save_markov.save_dirty().await;
+            }
+        });
+
         tokio::spawn(async move {
             loop {
                 tokio::time::sleep(Duration::from_secs(3600)).await;
-                {
-                    const MARKOV_CHANNEL: ChannelId = ChannelId::new(464502359372857355);
+
+                let configs = match GuildConfig::fetch_all(&postgres).await {
+                    Ok(configs) => configs,
+                    Err(err) => {
+                        error!("error getting guild configs: {err}");
+                        continue;
+                    }
+                };
+
+                for config in configs.iter() {
+                    let Some(markov_channel) = config.markov_channel() else {
+                        continue;
+                    };
                     let http = http.clone();
+                    let guild_markov = markov.for_guild(config.guild_id.try_into().ok()).await;
 
-                    let last_message = MARKOV_CHANNEL.messages_iter(&http).boxed().next().await;
+                    let last_message = markov_channel.messages_iter(&http).boxed().next().await;
                     if let Some(result) = last_message {
                         match result {
                             Ok(message) => {
                                 if message.author.id != cache.current_user().id {
-                                    loop {
-                                        let generated_message = markov.generate_string().await;
-                                        if generated_message.len() < 2000 {
-                                            MARKOV_CHANNEL
-                                                .send_message(
-                                                    &http,
-                                                    CreateMessage::new().content(generated_message),
-                                                )
-                                                .await
-                                                .expect("couldn't send message");
-                                            break;
-                                        }
+                                    let generated_message = guild_markov
+                                        .read()
+                                        .await
+                                        .generate_string(Bounds::MESSAGE, &mut rng.next())
+                                        .await;
+                                    if let Err(err) = markov_channel
+                                        .send_message(
+                                            &http,
+                                            CreateMessage::new()
+                                                .content(sanitize(
+                                                    &generated_message,
+                                                    config.markov_links(),
+                                                ))
+                                                .allowed_mentions(CreateAllowedMentions::new()),
+                                        )
+                                        .await
+                                    {
+                                        error!("couldn't send message: {err}");
                                     }
                                 }
                             }
@@ -1,31 +1,24 @@
 use anyhow::Result;
-use log::error;
-use serenity::all::CreateMessage;
+use serenity::all::{CreateMessage, Mentionable};
 use serenity::client::Context;
 use serenity::model::guild::{ScheduledEvent, ScheduledEventStatus};
 
-use super::EVENT_REPORT_CHANNEL;
+use crate::commands::config::GuildConfig;
 use crate::FrameworkContext;
 
 pub async fn handle(
-    _framework_ctx: FrameworkContext<'_>,
+    framework_ctx: FrameworkContext<'_>,
     ctx: &Context,
     event: &ScheduledEvent,
 ) -> Result<()> {
-    let report_channel_guild_id = match ctx.cache.guild_channel(EVENT_REPORT_CHANNEL) {
-        Some(val) => val.guild_id,
-        None => {
-            error!("could not get EVENT_REPORT_CHANNEL as guild channel");
-            return Ok(());
-        }
-    };
-    if event.guild_id != report_channel_guild_id {
+    let config = GuildConfig::fetch(event.guild_id, &framework_ctx.user_data.postgres).await?;
+    let Some(report_channel) = config.event_report_channel() else {
         return Ok(());
-    }
+    };
 
     match event.status {
         ScheduledEventStatus::Scheduled => {
-            EVENT_REPORT_CHANNEL
+            report_channel
                 .send_message(
                     &ctx,
                     CreateMessage::new().content(
@@ -39,13 +32,17 @@ pub async fn handle(
                 .await?;
         }
         ScheduledEventStatus::Active => {
-            EVENT_REPORT_CHANNEL
+            report_channel
                 .send_message(
                     &ctx,
                     CreateMessage::new().content(
                         String::from("Event **")
                             + &event.name
-                            + "** has started! <@&816024905061367829>",
+                            + "** has started!"
+                            + &config
+                                .event_ping_role()
+                                .map(|role| format!(" {}", role.mention()))
+                                .unwrap_or_default(),
                     ),
                 )
                 .await?;
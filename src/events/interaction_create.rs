@@ -1,10 +1,14 @@
 use anyhow::Result;
 use serenity::all::{Context, Interaction};
 
-use crate::commands::shares::perks::{on_buy_perk, on_perk_shop};
+use crate::commands::shares::give::on_give_confirm;
+use crate::commands::shares::perks::{
+    on_buy_perk, on_perk_shop, on_respec_confirm, RESPEC_CONFIRM_BUTTON,
+};
 use crate::commands::shares::{
-    on_buy_generator, on_collect, on_prestige, on_prestige_confirm, BUY_GENERATOR_BUTTON,
-    COLLECT_BUTTON, PRESTIGE_BUTTON, PRESTIGE_CONFIRM_BUTTON,
+    on_buy_generator, on_collect, on_prestige, on_prestige_confirm, BUY_10_GENERATORS_BUTTON,
+    BUY_GENERATOR_BUTTON, BUY_MAX_GENERATORS_BUTTON, COLLECT_BUTTON, PRESTIGE_BUTTON,
+    PRESTIGE_CONFIRM_BUTTON,
 };
 use crate::FrameworkContext;
 
@@ -28,7 +32,18 @@ pub async fn handle(
                 framework_ctx,
                 ctx,
                 interaction,
-                id.split(':').last().unwrap().parse()?,
+                id.split(':').last().unwrap(),
+            )
+            .await?;
+        } else if id.starts_with("give_confirm") {
+            let mut transfer = id.split(':').skip(1);
+            on_give_confirm(
+                framework_ctx,
+                ctx,
+                interaction,
+                transfer.next().unwrap_or_default().parse()?,
+                transfer.next().unwrap_or_default().parse()?,
+                transfer.next().unwrap_or_default().parse()?,
             )
             .await?;
         } else {
@@ -37,7 +52,13 @@ pub async fn handle(
                     on_collect(framework_ctx, ctx, interaction).await?;
                 }
                 BUY_GENERATOR_BUTTON => {
-                    on_buy_generator(framework_ctx, ctx, interaction).await?;
+                    on_buy_generator(framework_ctx, ctx, interaction, Some(1)).await?;
+                }
+                BUY_10_GENERATORS_BUTTON => {
+                    on_buy_generator(framework_ctx, ctx, interaction, Some(10)).await?;
+                }
+                BUY_MAX_GENERATORS_BUTTON => {
+                    on_buy_generator(framework_ctx, ctx, interaction, None).await?;
                 }
                 PRESTIGE_BUTTON => {
                     on_prestige(framework_ctx, ctx, interaction).await?;
@@ -45,6 +66,9 @@ pub async fn handle(
                 PRESTIGE_CONFIRM_BUTTON => {
                     on_prestige_confirm(framework_ctx, ctx, interaction).await?;
                 }
+                RESPEC_CONFIRM_BUTTON => {
+                    on_respec_confirm(framework_ctx, ctx, interaction).await?;
+                }
                 _ => (),
             };
         }
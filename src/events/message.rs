@@ -1,7 +1,13 @@
 use anyhow::Result;
+use serenity::all::{CreateAllowedMentions, CreateMessage};
 use serenity::client::Context;
 use serenity::model::channel::Message;
 
+use crate::commands::config::GuildConfig;
+use crate::commands::markov::is_opted_in;
+use crate::markov::sanitize::sanitize;
+use crate::markov::store::ModelKey;
+use crate::markov::Bounds;
 use crate::FrameworkContext;
 
 pub async fn handle(
@@ -9,12 +15,57 @@ pub async fn handle(
     ctx: &Context,
     new_message: &Message,
 ) -> Result<()> {
+    let data = framework_ctx.user_data;
+
     if new_message.mentions_me(&ctx).await? {
-        loop {
-            let reply = framework_ctx.user_data.markov.generate_string().await;
-            if reply.len() < 2000 {
-                new_message.reply_ping(&ctx, reply).await?;
-                break;
+        let markov = data
+            .markov
+            .for_guild(new_message.guild_id.map(|id| id.get()))
+            .await;
+        // skip mentions and short filler words so the reply is seeded from what the message is about
+        let seed = new_message
+            .content
+            .split_whitespace()
+            .filter(|word| !(word.starts_with('<') && word.ends_with('>')) && word.len() > 3)
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let reply = markov
+            .read()
+            .await
+            .generate_string_seeded(&seed, Bounds::MESSAGE, &mut data.rng.next())
+            .await;
+        let links = GuildConfig::fetch_markov_links(new_message.guild_id, &data.postgres).await?;
+        new_message
+            .channel_id
+            .send_message(
+                &ctx,
+                CreateMessage::new()
+                    .content(sanitize(&reply, links))
+                    .reference_message(new_message)
+                    .allowed_mentions(CreateAllowedMentions::new().replied_user(true)),
+            )
+            .await?;
+    } else if let Some(guild_id) = new_message.guild_id {
+        if !new_message.author.bot
+            && GuildConfig::is_markov_learn_channel(new_message.channel_id, &data.postgres).await?
+        {
+            data.markov
+                .get(ModelKey::Guild(guild_id.get()))
+                .await
+                .write()
+                .await
+                .learn(&new_message.content);
+
+            if is_opted_in(new_message.author.id, &data.postgres).await? {
+                data.markov
+                    .get(ModelKey::User {
+                        guild_id: guild_id.get(),
+                        user_id: new_message.author.id.get(),
+                    })
+                    .await
+                    .write()
+                    .await
+                    .learn(&new_message.content);
             }
         }
     }
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use serenity::all::{ChannelId, FullEvent};
+use serenity::all::FullEvent;
 
 use crate::FrameworkContext;
 
@@ -12,8 +12,6 @@ mod message;
 mod ready;
 mod voice_state_update;
 
-const EVENT_REPORT_CHANNEL: ChannelId = ChannelId::new(924343631761006592);
-
 pub async fn handle(framework_ctx: FrameworkContext<'_>, event: &FullEvent) -> Result<()> {
     match event {
         FullEvent::Ready {
@@ -1,31 +1,40 @@
 use anyhow::Result;
-use serenity::all::{GuildId, UserId, VoiceState};
+use serenity::all::VoiceState;
 use serenity::client::Context;
 
+use crate::commands::config::GuildConfig;
 use crate::FrameworkContext;
 
-const RP_GUILD: GuildId = GuildId::new(464502358924197912);
-const ME: UserId = UserId::new(392352456303968256);
+/// How many members a voice channel needs before the alert user is messaged.
+const VOICE_ALERT_THRESHOLD: usize = 4;
 
 pub async fn handle(
-    _framework_ctx: FrameworkContext<'_>,
+    framework_ctx: FrameworkContext<'_>,
     ctx: &Context,
     _old: &Option<VoiceState>,
     new: &VoiceState,
 ) -> Result<()> {
-    if new.guild_id.is_some_and(|id| id == RP_GUILD) {
-        if let Some(channel_id) = new.channel_id {
-            let channel = ctx.http.get_channel(channel_id).await?;
-            if channel.guild().is_some_and(|channel| {
-                channel.members(ctx.cache.clone()).is_ok_and(|members| {
-                    members.len() >= 4 && !members.iter().any(|member| member.user.id == ME)
-                })
-            }) {
-                ME.create_dm_channel(&ctx.http)
-                    .await?
-                    .say(&ctx.http, "IT'S TIME")
-                    .await?;
-            }
+    let Some(guild_id) = new.guild_id else {
+        return Ok(());
+    };
+    let config = GuildConfig::fetch(guild_id, &framework_ctx.user_data.postgres).await?;
+    let Some(alert_user) = config.voice_alert_user() else {
+        return Ok(());
+    };
+
+    if let Some(channel_id) = new.channel_id {
+        let channel = ctx.http.get_channel(channel_id).await?;
+        if channel.guild().is_some_and(|channel| {
+            channel.members(ctx.cache.clone()).is_ok_and(|members| {
+                members.len() >= VOICE_ALERT_THRESHOLD
+                    && !members.iter().any(|member| member.user.id == alert_user)
+            })
+        }) {
+            alert_user
+                .create_dm_channel(&ctx.http)
+                .await?
+                .say(&ctx.http, "IT'S TIME")
+                .await?;
         }
     }
 
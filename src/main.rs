@@ -12,12 +12,16 @@ use serenity::prelude::*;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 
+use crate::commands::roll::RollLimits;
+use crate::markov::store::MarkovStore;
 use crate::markov::Markov;
+use crate::rng::RngSource;
 
 mod commands;
 mod error;
 mod events;
 mod markov;
+mod rng;
 
 #[derive(Debug)]
 pub struct DataWrapper(Arc<Data>);
@@ -26,10 +30,12 @@ type FrameworkContext<'a> = poise::FrameworkContext<'a, DataWrapper, Error>;
 
 #[derive(Debug)]
 pub struct Data {
-    markov: Arc<Markov>,
+    markov: Arc<MarkovStore>,
     markov_loop_running: AtomicBool,
     reqwest: Reqwest,
     postgres: PgPool,
+    roll_limits: RollLimits,
+    rng: Arc<RngSource>,
 }
 
 impl Deref for DataWrapper {
@@ -40,14 +46,18 @@ impl Deref for DataWrapper {
     }
 }
 
-impl TypeMapKey for Markov {
-    type Value = Arc<Markov>;
+impl TypeMapKey for MarkovStore {
+    type Value = Arc<MarkovStore>;
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
 
+    if env::args().nth(1).as_deref() == Some("ingest") {
+        return markov::ingest::run(env::args().skip(2));
+    }
+
     let token = env::var("DISCORD_TOKEN").expect("could not get discord token");
 
     let intents = GatewayIntents::GUILD_MESSAGES
@@ -56,6 +66,16 @@ async fn main() -> Result<()> {
         | GatewayIntents::GUILD_SCHEDULED_EVENTS
         | GatewayIntents::GUILD_VOICE_STATES;
 
+    let postgres = PgPoolOptions::new()
+        .connect(&env::var("DATABASE_URL")?)
+        .await?;
+    sqlx::migrate!().run(&postgres).await?;
+
+    let markov = Arc::new(MarkovStore::new(Markov::new(2, "message-dump.txt", true)));
+    let framework_markov = markov.clone();
+    let roll_limits = RollLimits::from_env()?;
+    let rng = Arc::new(RngSource::from_env()?);
+
     let framework = poise::Framework::new(
         poise::FrameworkOptions {
             commands: commands::commands(),
@@ -74,15 +94,15 @@ async fn main() -> Result<()> {
             },
             ..poise::FrameworkOptions::default()
         },
-        |_, _, _| {
-            Box::pin(async {
+        move |_, _, _| {
+            Box::pin(async move {
                 Ok(DataWrapper(Arc::new(Data {
-                    markov: Arc::new(Markov::new(2, "message-dump.txt", true)),
+                    markov: framework_markov,
                     markov_loop_running: AtomicBool::new(false),
                     reqwest: ReqwestBuilder::new().pool_max_idle_per_host(1).build()?,
-                    postgres: PgPoolOptions::new()
-                        .connect(&env::var("DATABASE_URL")?)
-                        .await?,
+                    postgres,
+                    roll_limits,
+                    rng,
                 })))
             })
         },
@@ -93,9 +113,20 @@ async fn main() -> Result<()> {
         .await
         .expect("error creating client");
 
+    let shard_manager = client.shard_manager.clone();
+    tokio::spawn(async move {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("couldn't listen for ctrl-c: {err}");
+            return;
+        }
+        shard_manager.shutdown_all().await;
+    });
+
     if let Err(why) = client.start().await {
         error!("client error: {:?}", why);
     }
 
+    markov.save_dirty().await;
+
     Ok(())
 }
@@ -1,15 +1,197 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use log::{error, info, warn};
-use rand::{thread_rng, Rng};
-use serde::{Deserialize, Serialize};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Debug, Serialize, Deserialize)]
+mod format;
+pub mod ingest;
+pub mod sanitize;
+pub mod store;
+
+const DATA_PATH: &str = "markov_data";
+
+/// How long a generated string is allowed to be.
+#[derive(Clone, Copy, Debug)]
+pub struct Bounds {
+    pub min_chars: usize,
+    pub max_chars: usize,
+    pub min_sentences: usize,
+    pub max_sentences: usize,
+}
+
+impl Bounds {
+    /// Anything that fits in a single Discord message.
+    pub const MESSAGE: Self = Self {
+        min_chars: 1,
+        max_chars: 2000,
+        min_sentences: 1,
+        max_sentences: 10,
+    };
+
+    fn met_by(&self, chars: usize, sentences: usize) -> bool {
+        chars >= self.min_chars && sentences >= self.min_sentences
+    }
+}
+
+/// Index of a word in `Markov::words`.
+type WordId = u32;
+
+/// The words that can follow a key, and how often each was seen after it.
+///
+/// Each entry holds a word and the combined weight of it and every entry before it,
+/// so sampling is a binary search. Most keys only ever see one successor,
+/// which is stored inline instead of allocating.
+#[derive(Debug)]
+enum Successors {
+    One((WordId, u32)),
+    Many(Vec<(WordId, u32)>),
+}
+
+impl Successors {
+    fn new(word: WordId, weight: u32) -> Self {
+        Self::One((word, weight))
+    }
+
+    fn entries(&self) -> &[(WordId, u32)] {
+        match self {
+            Self::One(entry) => std::slice::from_ref(entry),
+            Self::Many(entries) => entries,
+        }
+    }
+
+    fn add(&mut self, word: WordId, weight: u32) {
+        match self {
+            Self::One((id, total)) if *id == word => *total = total.saturating_add(weight),
+            Self::One(entry) => {
+                *self = Self::Many(vec![*entry, (word, entry.1.saturating_add(weight))]);
+            }
+            Self::Many(entries) => match entries.iter().position(|(id, _)| *id == word) {
+                Some(i) => {
+                    for (_, total) in &mut entries[i..] {
+                        *total = total.saturating_add(weight);
+                    }
+                }
+                None => {
+                    let total = entries.last().map_or(0, |(_, total)| *total);
+                    entries.push((word, total.saturating_add(weight)));
+                }
+            },
+        }
+    }
+
+    fn total(&self) -> u32 {
+        self.entries().last().map_or(0, |(_, total)| *total)
+    }
+
+    fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    /// Pick a word, each with a chance proportional to its weight.
+    fn sample(&self, rng: &mut impl Rng) -> WordId {
+        let entries = self.entries();
+        if let [(word, _)] = entries {
+            return *word;
+        }
+
+        let target = rng.gen_range(0..self.total());
+        entries[entries.partition_point(|(_, total)| *total <= target)].0
+    }
+
+    /// Iterate over every word and its weight.
+    fn iter(&self) -> impl Iterator<Item = (WordId, u32)> + '_ {
+        let previous = std::iter::once(0).chain(self.entries().iter().map(|(_, total)| *total));
+        self.entries()
+            .iter()
+            .zip(previous)
+            .map(|((word, total), previous)| (*word, total - previous))
+    }
+}
+
+/// Saved as a list of words and their weights, rather than running totals.
+/// Words added after the weights saturated have none and are left out.
+impl Serialize for Successors {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter().filter(|(_, weight)| *weight > 0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Successors {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let weights = Vec::<(WordId, u32)>::deserialize(deserializer)?;
+        if weights.iter().any(|(_, weight)| *weight == 0) {
+            return Err(D::Error::custom("successor weights must be positive"));
+        }
+
+        let ((word, weight), rest) = weights
+            .split_first()
+            .ok_or_else(|| D::Error::custom("a key must have at least one successor"))?;
+        let mut successors = Self::new(*word, *weight);
+        for (word, weight) in rest {
+            successors.add(*word, *weight);
+        }
+        Ok(successors)
+    }
+}
+
+#[derive(Debug)]
 pub struct Markov {
-    data: HashMap<String, Vec<String>>,
-    start_keys: Vec<String>,
+    /// Every word the chain knows, indexed by `WordId`.
+    words: Vec<Box<str>>,
+    word_ids: HashMap<Box<str>, WordId>,
+    /// Every word with the same `normalize_word`, so seeds can be looked up without scanning `words`.
+    normalized_ids: HashMap<Box<str>, Vec<WordId>>,
+    chain: HashMap<Box<[WordId]>, Successors>,
+    /// The key each learned message started with.
+    start_keys: Vec<Box<[WordId]>>,
     key_size: u8,
+    /// Whether the chain has learned anything since it was last saved.
+    dirty: AtomicBool,
+    /// Where the chain is saved to.
+    path: PathBuf,
+    /// How long the chain took to build or load.
+    load_time: Duration,
+}
+
+/// Numbers describing a `Markov` chain.
+#[derive(Debug)]
+pub struct Stats {
+    pub words: usize,
+    pub keys: usize,
+    pub transitions: usize,
+    pub unique_transitions: usize,
+    pub start_keys: usize,
+    pub key_size: u8,
+    /// Size of the saved chain in bytes, if it has been saved.
+    pub file_size: Option<u64>,
+    pub load_time: Duration,
+}
+
+/// A chain encoded for saving, so it can be written to disk without holding on to the chain.
+#[derive(Debug)]
+pub struct Snapshot {
+    data: Vec<u8>,
+    path: PathBuf,
+}
+
+impl Snapshot {
+    /// Write the chain to disk, only replacing the old file once the new one is complete.
+    pub fn write(&self) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, &self.data)?;
+        fs::rename(&tmp_path, &self.path)
+    }
 }
 
 impl Markov {
@@ -17,91 +199,598 @@ impl Markov {
         let start_time = std::time::Instant::now();
 
         if should_import {
-            if let Ok(val) = fs::read("markov_data") {
-                match rmp_serde::from_slice::<Markov>(&val) {
-                    Ok(val) => {
-                        info!(
-                            "Markov data serialized in: {:?}",
-                            std::time::Instant::now().duration_since(start_time)
-                        );
-                        if key_size != val.key_size {
-                            warn!("Asked to construct a Markov instance with key_size {}, but imported one with {} instead.", key_size, val.key_size);
-                        }
-                        return val;
-                    }
-                    Err(err) => error!("couldn't serialize found markov data: {err}"),
-                }
+            if let Some(markov) = Self::import(key_size, Path::new(DATA_PATH)) {
+                return markov;
             }
         }
 
-        let mut markov: HashMap<String, Vec<String>> = HashMap::new();
-        let mut start_keys: Vec<String> = vec![];
+        let mut markov = Self::empty(key_size, DATA_PATH);
         for line in fs::read_to_string(path)
             .expect("couldn't read message dump")
             .trim_end()
             .split('\n')
         {
-            let words: Vec<&str> = line.split_whitespace().collect();
-            for i in 0..words.len().saturating_sub(key_size as usize) {
-                let mut key = words[i].to_string();
-                for word in words.iter().take(i + key_size as usize).skip(i + 1) {
-                    key += " ";
-                    key += word;
+            markov.learn(line);
+        }
+
+        markov.load_time = std::time::Instant::now().duration_since(start_time);
+        info!("Markov data built in: {:?}", markov.load_time);
+
+        markov.save();
+
+        markov
+    }
+
+    /// Load the chain saved at `path`, or start an empty one there if there is none.
+    pub fn load(key_size: u8, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+
+        Self::import(key_size, &path).unwrap_or_else(|| Self::empty(key_size, path))
+    }
+
+    fn empty(key_size: u8, path: impl Into<PathBuf>) -> Self {
+        Self {
+            words: vec![],
+            word_ids: HashMap::new(),
+            normalized_ids: HashMap::new(),
+            chain: HashMap::new(),
+            start_keys: vec![],
+            key_size,
+            dirty: AtomicBool::new(false),
+            path: path.into(),
+            load_time: Duration::ZERO,
+        }
+    }
+
+    fn import(key_size: u8, path: &Path) -> Option<Self> {
+        let start_time = std::time::Instant::now();
+
+        let val = fs::read(path).ok()?;
+        let mut markov = match format::decode(&val) {
+            Ok(format::Decoded::Current(saved)) => {
+                let mut markov = Self::empty(saved.key_size, path);
+                markov.word_ids = saved
+                    .words
+                    .iter()
+                    .enumerate()
+                    .map(|(id, word)| (word.clone(), id as WordId))
+                    .collect();
+                for (id, word) in saved.words.iter().enumerate() {
+                    markov.index_normalized(word, id as WordId);
+                }
+                markov.words = saved.words;
+                markov.chain = saved.chain;
+                markov.start_keys = saved.start_keys;
+                if !markov.is_valid() {
+                    error!("found markov data at {path:?} is corrupted");
+                    return None;
                 }
-                if i == 0 {
-                    start_keys.push(key.clone());
+                markov
+            }
+            Ok(format::Decoded::Legacy(legacy)) => {
+                info!("converting markov data at {path:?} from the legacy format");
+                let mut markov = Self::empty(legacy.key_size, path);
+                for (key, successors) in legacy.data {
+                    let key = markov.intern_all(key.split(' '));
+                    let successors = markov.intern_all(successors.iter().map(String::as_str));
+                    let Some((first, rest)) = successors.split_first() else {
+                        continue;
+                    };
+                    let mut entry = Successors::new(*first, 1);
+                    for word in rest {
+                        entry.add(*word, 1);
+                    }
+                    markov.chain.insert(key, entry);
                 }
-                let value = words[i + key_size as usize];
-                if let Some(val) = markov.get_mut(&key) {
-                    val.push(value.to_string());
-                } else {
-                    markov.insert(key, vec![value.to_string()]);
+                for key in legacy.start_keys {
+                    let key = markov.intern_all(key.split(' '));
+                    markov.start_keys.push(key);
                 }
+                // write it back in the current format next time chains are saved
+                markov.dirty.store(true, Ordering::Relaxed);
+                markov
+            }
+            Err(err) => {
+                error!("couldn't serialize found markov data: {err}");
+                return None;
             }
+        };
+
+        markov.load_time = std::time::Instant::now().duration_since(start_time);
+        info!("Markov data serialized in: {:?}", markov.load_time);
+        if key_size != markov.key_size {
+            warn!("Asked to construct a Markov instance with key_size {}, but imported one with {} instead.", key_size, markov.key_size);
         }
 
-        info!(
-            "Markov data built in: {:?}",
-            std::time::Instant::now().duration_since(start_time)
-        );
+        Some(markov)
+    }
 
-        let markov = Self {
-            data: markov,
-            start_keys,
-            key_size,
+    /// Check that every word ID points at a known word and every key is the right size,
+    /// so a damaged file can't make generation panic.
+    fn is_valid(&self) -> bool {
+        let words = self.words.len();
+        let key_is_valid = |key: &[WordId]| {
+            key.len() == self.key_size as usize && key.iter().all(|id| (*id as usize) < words)
         };
 
-        match rmp_serde::to_vec(&markov) {
-            Ok(val) => match fs::write("markov_data", val) {
-                Ok(_) => (),
-                Err(err) => error!("couldn't write markov data: {err}"),
-            },
-            Err(err) => error!("couldn't serialize markov data: {err}"),
+        self.start_keys.iter().all(|key| key_is_valid(key))
+            && self.chain.iter().all(|(key, successors)| {
+                key_is_valid(key)
+                    && successors
+                        .entries()
+                        .iter()
+                        .all(|(id, _)| (*id as usize) < words)
+            })
+    }
+
+    fn intern(&mut self, word: &str) -> WordId {
+        if let Some(id) = self.word_ids.get(word) {
+            return *id;
+        }
+
+        let id = self.words.len() as WordId;
+        self.words.push(word.into());
+        self.word_ids.insert(word.into(), id);
+        self.index_normalized(word, id);
+        id
+    }
+
+    fn index_normalized(&mut self, word: &str, id: WordId) {
+        self.normalized_ids
+            .entry(normalize_word(word).into())
+            .or_default()
+            .push(id);
+    }
+
+    fn intern_all<'a>(&mut self, words: impl Iterator<Item = &'a str>) -> Box<[WordId]> {
+        words.map(|word| self.intern(word)).collect()
+    }
+
+    fn word(&self, id: WordId) -> &str {
+        &self.words[id as usize]
+    }
+
+    /// Whether the chain has not learned anything yet.
+    pub fn is_empty(&self) -> bool {
+        self.start_keys.is_empty()
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            words: self.words.len(),
+            keys: self.chain.len(),
+            transitions: self
+                .chain
+                .values()
+                .map(|successors| successors.total() as usize)
+                .sum(),
+            unique_transitions: self.chain.values().map(Successors::len).sum(),
+            start_keys: self.start_keys.len(),
+            key_size: self.key_size,
+            file_size: fs::metadata(&self.path).ok().map(|metadata| metadata.len()),
+            load_time: self.load_time,
+        }
+    }
+
+    /// Get the words that can follow `key` and how many times each was seen, most common first.
+    /// Returns `None` if `key` is not in the chain.
+    pub fn successors(&self, key: &str) -> Option<Vec<(&str, usize)>> {
+        let key = key
+            .split_whitespace()
+            .map(|word| self.word_ids.get(word).copied())
+            .collect::<Option<Vec<WordId>>>()?;
+
+        let mut successors: Vec<(&str, usize)> = self
+            .chain
+            .get(key.as_slice())?
+            .iter()
+            .map(|(word, weight)| (self.word(word), weight as usize))
+            .collect();
+        successors.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        Some(successors)
+    }
+
+    /// How many words make up a key.
+    pub fn key_size(&self) -> u8 {
+        self.key_size
+    }
+
+    /// Add a single message to the chain.
+    pub fn learn(&mut self, line: &str) {
+        let key_size = self.key_size as usize;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() <= key_size {
+            return;
+        }
+
+        let words = self.intern_all(words.into_iter());
+        self.start_keys.push(words[..key_size].into());
+        for i in 0..words.len() - key_size {
+            let key = &words[i..i + key_size];
+            let value = words[i + key_size];
+            if let Some(successors) = self.chain.get_mut(key) {
+                successors.add(value, 1);
+            } else {
+                self.chain.insert(key.into(), Successors::new(value, 1));
+            }
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the chain has changed since it was last written to disk.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    /// Count the chain as changed since it was last saved, e.g. because writing a snapshot failed.
+    pub fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Encode the chain for saving, counting it as saved from now on.
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        match format::encode(self.key_size, &self.words, &self.chain, &self.start_keys) {
+            Ok(data) => {
+                self.dirty.store(false, Ordering::Relaxed);
+                Some(Snapshot {
+                    data,
+                    path: self.path.clone(),
+                })
+            }
+            Err(err) => {
+                error!("couldn't serialize markov data: {err}");
+                None
+            }
+        }
+    }
+
+    /// Write the chain to disk.
+    pub fn save(&self) {
+        if let Some(snapshot) = self.snapshot() {
+            if let Err(err) = snapshot.write() {
+                error!("couldn't write markov data: {err}");
+                self.mark_dirty();
+            }
+        }
+    }
+
+    /// Generate a string from a random start key, or an empty string if the chain hasn't learned anything.
+    pub async fn generate_string(&self, bounds: Bounds, rng: &mut impl Rng) -> String {
+        match self.start_keys.choose(rng) {
+            Some(start_key) => self.generate_from(start_key, bounds, rng),
+            None => String::new(),
+        }
+    }
+
+    /// Generate a string starting from a key containing the words in `seed`.
+    /// Keys containing every word are preferred over keys containing any of them,
+    /// and a random start key is used if no key contains any.
+    pub async fn generate_string_seeded(
+        &self,
+        seed: &str,
+        bounds: Bounds,
+        rng: &mut impl Rng,
+    ) -> String {
+        let mut seeds: Vec<String> = seed
+            .split_whitespace()
+            .map(normalize_word)
+            .filter(|word| !word.is_empty())
+            .collect();
+        seeds.sort_unstable();
+        seeds.dedup();
+        // one bit per seed word below
+        seeds.truncate(u64::BITS as usize);
+        if seeds.is_empty() {
+            return self.generate_string(bounds, rng).await;
+        }
+
+        // which seed word each known word matches, for the words that match any
+        let mut word_matches: HashMap<WordId, u64> = HashMap::new();
+        for (i, seed) in seeds.iter().enumerate() {
+            for id in self.normalized_ids.get(seed.as_str()).into_iter().flatten() {
+                *word_matches.entry(*id).or_default() |= 1 << i;
+            }
+        }
+        if word_matches.is_empty() {
+            return self.generate_string(bounds, rng).await;
+        }
+        let all_seeds = u64::MAX >> (u64::BITS as usize - seeds.len());
+        let key_matches = |key: &[WordId]| {
+            key.iter().fold(0, |matches, id| {
+                matches | word_matches.get(id).copied().unwrap_or(0)
+            })
         };
 
+        let mut keys: Vec<&[WordId]> = self
+            .chain
+            .keys()
+            .map(|key| &**key)
+            .filter(|key| key_matches(key) == all_seeds)
+            .collect();
+        if keys.is_empty() {
+            keys = self
+                .chain
+                .keys()
+                .map(|key| &**key)
+                .filter(|key| key_matches(key) != 0)
+                .collect();
+        }
+
+        match keys.choose(rng) {
+            Some(key) => self.generate_from(key, bounds, rng),
+            None => self.generate_string(bounds, rng).await,
+        }
+    }
+
+    /// Walk the chain from `start_key` until `bounds` are satisfied.
+    ///
+    /// Every step either adds a word or stops, so this always finishes within `bounds.max_chars` steps.
+    /// If the chain runs out before the minimum bounds are met, it continues from a random start key.
+    /// If the maximum length is hit mid-sentence, the output is cut back to the last sentence end that met the minimum bounds.
+    fn generate_from(&self, start_key: &[WordId], bounds: Bounds, rng: &mut impl Rng) -> String {
+        let mut out: Vec<WordId> = Vec::new();
+        let mut chars: usize = 0;
+        let mut sentences: usize = 0;
+        let mut sentence_ended = false;
+        // how many words of `out` to keep to end on the last sentence end that met the minimum bounds
+        let mut last_end: Option<usize> = None;
+
+        let mut pending: VecDeque<WordId> = start_key.iter().copied().collect();
+        loop {
+            let id = match pending.pop_front() {
+                Some(id) => id,
+                None => {
+                    let key = &out[out.len().saturating_sub(self.key_size as usize)..];
+                    match self.chain.get(key) {
+                        Some(successors) => successors.sample(rng),
+                        None => {
+                            // the message this part of the chain was learned from ended here
+                            if !sentence_ended {
+                                sentences += 1;
+                                sentence_ended = true;
+                                if bounds.met_by(chars, sentences) {
+                                    last_end = Some(out.len());
+                                }
+                            }
+                            if bounds.met_by(chars, sentences) || sentences >= bounds.max_sentences
+                            {
+                                break;
+                            }
+                            match self.start_keys.choose(rng) {
+                                Some(start_key) => pending.extend(start_key.iter()),
+                                None => break,
+                            }
+                            continue;
+                        }
+                    }
+                }
+            };
+            let word = self.word(id);
+
+            let added = word.chars().count() + usize::from(!out.is_empty());
+            if chars + added > bounds.max_chars {
+                if out.is_empty() {
+                    return word.chars().take(bounds.max_chars).collect();
+                }
+                if let Some(end) = last_end {
+                    out.truncate(end);
+                }
+                break;
+            }
+
+            out.push(id);
+            chars += added;
+            sentence_ended = ends_sentence(word);
+            if sentence_ended {
+                sentences += 1;
+                if bounds.met_by(chars, sentences) {
+                    last_end = Some(out.len());
+                }
+                if sentences >= bounds.max_sentences {
+                    break;
+                }
+            }
+        }
+
+        out.iter()
+            .map(|id| self.word(*id))
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+}
+
+/// Whether `word` is the last word of a sentence.
+fn ends_sentence(word: &str) -> bool {
+    word.trim_end_matches(['"', '\'', ')', '*', '_'])
+        .ends_with(['.', '!', '?', '…'])
+}
+
+/// Lowercase `word` and strip punctuation around it, so that e.g. "Hello," matches "hello".
+fn normalize_word(word: &str) -> String {
+    word.trim_matches(|char: char| !char.is_alphanumeric())
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::{ends_sentence, format, Bounds, Markov, Successors};
+
+    fn markov(key_size: u8, lines: &[&str]) -> Markov {
+        let mut markov = Markov::empty(key_size, "unused");
+        for line in lines {
+            markov.learn(line);
+        }
         markov
     }
 
-    pub async fn generate_string(&self) -> String {
-        const MAX_LEN: u16 = 1600;
+    /// A file to save a chain to that no other test uses.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("markov_test_{}_{name}", std::process::id()))
+    }
+
+    #[test]
+    fn saved_chains() {
+        let path = temp_path("saved");
+        let mut saved = markov(1, &["a b a c", "b a b"]);
+        saved.path = path.clone();
+        saved.save();
+        assert!(!saved.is_dirty());
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        assert!(!Path::new(&tmp_path).exists());
 
-        let mut key = self
-            .start_keys
-            .get(thread_rng().gen_range(0..self.start_keys.len()))
-            .unwrap()
-            .split(' ')
-            .collect::<Vec<&str>>();
+        let loaded = Markov::import(1, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(!loaded.is_dirty());
+        assert_eq!(loaded.words, saved.words);
+        assert_eq!(loaded.start_keys, saved.start_keys);
+        for key in ["a", "b", "c"] {
+            assert_eq!(loaded.successors(key), saved.successors(key));
+        }
+    }
 
-        let mut out: Vec<&str> = key.clone();
+    #[test]
+    fn corrupt_import() {
+        let path = temp_path("corrupt");
+        let words: Vec<Box<str>> = vec!["a".into()];
+        // word 5 doesn't exist
+        let chain = HashMap::from([(vec![0].into_boxed_slice(), Successors::new(5, 1))]);
+        let start_keys = vec![vec![0].into_boxed_slice()];
+        fs::write(
+            &path,
+            format::encode(1, &words, &chain, &start_keys).unwrap(),
+        )
+        .unwrap();
 
-        while let Some(val) = self.data.get(&*key.join(" ")) {
-            if out.len() > MAX_LEN as usize {
-                break;
+        assert!(Markov::import(1, &path).is_none());
+        // loading starts over rather than panicking later
+        assert!(Markov::load(1, &path).is_empty());
+        fs::remove_file(&path).unwrap();
+
+        fs::write(&path, b"garbage").unwrap();
+        assert!(Markov::import(1, &path).is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn empty_chain() {
+        let markov = markov(2, &["too short"]);
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(markov.is_empty());
+        assert_eq!(markov.generate_string(Bounds::MESSAGE, &mut rng).await, "");
+        assert_eq!(
+            markov
+                .generate_string_seeded("short", Bounds::MESSAGE, &mut rng)
+                .await,
+            ""
+        );
+    }
+
+    #[tokio::test]
+    async fn length_bounds() {
+        let markov = markov(
+            1,
+            &[
+                "the cat sat on the mat. the dog sat on the cat!",
+                "a dog is a good dog. is it? it is.",
+                "the mat is red and the cat is black",
+            ],
+        );
+        let bounds = Bounds {
+            min_chars: 10,
+            max_chars: 40,
+            min_sentences: 1,
+            max_sentences: 2,
+        };
+        for seed in 0..200 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let string = markov.generate_string(bounds, &mut rng).await;
+            assert!(!string.is_empty());
+            assert!(string.chars().count() <= bounds.max_chars, "{string:?}");
+            let sentences = string.split(' ').filter(|word| ends_sentence(word)).count();
+            assert!(sentences <= bounds.max_sentences, "{string:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn seeded() {
+        let lines = [
+            "the Cat sat down.",
+            "a dog ran off.",
+            "my dog, Rex, barked.",
+        ];
+        let path = temp_path("seeded");
+        let mut learned = markov(2, &lines);
+        learned.path = path.clone();
+        learned.save();
+        let loaded = Markov::import(2, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // seeds are found the same way in a chain that was loaded as one that learned them
+        for markov in [&learned, &loaded] {
+            for seed in 0..20 {
+                let mut rng = StdRng::seed_from_u64(seed);
+                assert_eq!(
+                    markov
+                        .generate_string_seeded("SAT!", Bounds::MESSAGE, &mut rng)
+                        .await,
+                    "Cat sat down."
+                );
+                // only one key has both words
+                assert_eq!(
+                    markov
+                        .generate_string_seeded("dog rex", Bounds::MESSAGE, &mut rng)
+                        .await,
+                    "dog, Rex, barked."
+                );
             }
-            out.push(&val[thread_rng().gen_range(0..val.len())]);
-            key = Vec::from(&out[out.len().saturating_sub(self.key_size as usize)..out.len()]);
+        }
+    }
+
+    #[tokio::test]
+    async fn cycles_terminate() {
+        // every word has a successor and no sentence ever ends, so only the length limit stops it
+        let markov = markov(1, &["round and round and round"]);
+        let bounds = Bounds {
+            min_chars: 1,
+            max_chars: 100,
+            min_sentences: 1,
+            max_sentences: 1,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let string = markov.generate_string(bounds, &mut rng).await;
+        assert!(string.starts_with("round and round"));
+        assert!(string.chars().count() <= bounds.max_chars);
+        assert!(string.chars().count() > bounds.max_chars - "round ".len());
+    }
+
+    #[test]
+    fn saturating_weights() {
+        let mut successors = Successors::new(0, u32::MAX - 1);
+        successors.add(0, 5);
+        assert_eq!(successors.total(), u32::MAX);
+        // weights past the most a chain can count are dropped rather than overflowing
+        successors.add(1, 1);
+        successors.add(0, 1);
+        assert_eq!(
+            successors.iter().collect::<Vec<_>>(),
+            [(0, u32::MAX), (1, 0)]
+        );
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..100 {
+            assert_eq!(successors.sample(&mut rng), 0);
         }
 
-        out.join(" ")
+        let saved = rmp_serde::to_vec(&successors).unwrap();
+        let loaded: Successors = rmp_serde::from_slice(&saved).unwrap();
+        assert_eq!(loaded.iter().collect::<Vec<_>>(), [(0, u32::MAX)]);
     }
 }
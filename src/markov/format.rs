@@ -0,0 +1,161 @@
+//! How chains are laid out in `markov_data` and the per-model files.
+//!
+//! Files start with a version number so the layout can change without breaking old saves.
+//! Files written before versioning was added have no version and are read as `Legacy`.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{Successors, WordId};
+
+/// The version written by `encode`. Bump it whenever `Saved` changes.
+const VERSION: u32 = 2;
+
+#[derive(Serialize)]
+struct SavedRef<'a> {
+    version: u32,
+    key_size: u8,
+    words: &'a [Box<str>],
+    chain: &'a HashMap<Box<[WordId]>, Successors>,
+    start_keys: &'a [Box<[WordId]>],
+}
+
+#[derive(Deserialize)]
+pub(super) struct Saved {
+    version: u32,
+    pub key_size: u8,
+    pub words: Vec<Box<str>>,
+    pub chain: HashMap<Box<[WordId]>, Successors>,
+    pub start_keys: Vec<Box<[WordId]>>,
+}
+
+/// The unversioned layout, which stored every successor of a key as its own string.
+#[derive(Deserialize)]
+pub(super) struct Legacy {
+    pub data: HashMap<String, Vec<String>>,
+    pub start_keys: Vec<String>,
+    pub key_size: u8,
+}
+
+pub(super) enum Decoded {
+    Current(Saved),
+    Legacy(Legacy),
+}
+
+pub(super) fn encode(
+    key_size: u8,
+    words: &[Box<str>],
+    chain: &HashMap<Box<[WordId]>, Successors>,
+    start_keys: &[Box<[WordId]>],
+) -> Result<Vec<u8>> {
+    Ok(rmp_serde::to_vec(&SavedRef {
+        version: VERSION,
+        key_size,
+        words,
+        chain,
+        start_keys,
+    })?)
+}
+
+pub(super) fn decode(bytes: &[u8]) -> Result<Decoded> {
+    match rmp_serde::from_slice::<Saved>(bytes) {
+        Ok(saved) if saved.version == VERSION => Ok(Decoded::Current(saved)),
+        Ok(saved) => bail!("unsupported markov data version {}", saved.version),
+        Err(err) => match rmp_serde::from_slice::<Legacy>(bytes) {
+            Ok(legacy) => Ok(Decoded::Legacy(legacy)),
+            // the legacy layout failing too is less interesting than why the current one did
+            Err(_) => Err(err.into()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+
+    use super::{decode, encode, Decoded, Successors, VERSION};
+    use crate::markov::Markov;
+
+    #[test]
+    fn round_trip() {
+        let words: Vec<Box<str>> = vec!["a".into(), "b".into(), "c".into()];
+        let mut successors = Successors::new(1, 2);
+        successors.add(2, 1);
+        let chain = HashMap::from([(vec![0].into_boxed_slice(), successors)]);
+        let start_keys = vec![vec![0].into_boxed_slice()];
+
+        let Decoded::Current(saved) =
+            decode(&encode(1, &words, &chain, &start_keys).unwrap()).unwrap()
+        else {
+            panic!("decoded as legacy");
+        };
+        assert_eq!(saved.version, VERSION);
+        assert_eq!(saved.key_size, 1);
+        assert_eq!(saved.words, words);
+        assert_eq!(saved.start_keys, start_keys);
+        assert_eq!(
+            saved.chain[&[0][..]].iter().collect::<Vec<_>>(),
+            [(1, 2), (2, 1)]
+        );
+    }
+
+    #[test]
+    fn legacy() {
+        // saved before versioning as `Markov { data, start_keys, key_size }`
+        let data = HashMap::from([
+            (
+                "a".to_string(),
+                vec!["b".to_string(), "c".to_string(), "b".to_string()],
+            ),
+            ("b".to_string(), vec!["a".to_string()]),
+        ]);
+        let bytes = rmp_serde::to_vec(&(data, vec!["a".to_string()], 1_u8)).unwrap();
+
+        let Decoded::Legacy(legacy) = decode(&bytes).unwrap() else {
+            panic!("decoded as current");
+        };
+        assert_eq!(legacy.key_size, 1);
+        assert_eq!(legacy.start_keys, ["a"]);
+        assert_eq!(legacy.data["a"], ["b", "c", "b"]);
+
+        let path = std::env::temp_dir().join(format!("markov_test_{}_legacy", std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let markov = Markov::import(1, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        // it is written back in the current format on the next save
+        assert!(markov.is_dirty());
+        assert!(markov.is_valid());
+        assert_eq!(markov.successors("a").unwrap(), [("b", 2), ("c", 1)]);
+        assert_eq!(markov.successors("b").unwrap(), [("a", 1)]);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(decode(b"not markov data").is_err());
+        assert!(decode(&[]).is_err());
+
+        let newer = rmp_serde::to_vec(&(
+            VERSION + 1,
+            1_u8,
+            Vec::<String>::new(),
+            HashMap::<Vec<u32>, Vec<(u32, u32)>>::new(),
+            Vec::<Vec<u32>>::new(),
+        ))
+        .unwrap();
+        assert!(decode(&newer).is_err());
+
+        // successors must have a positive weight
+        let zero_weight = rmp_serde::to_vec(&(
+            VERSION,
+            1_u8,
+            vec!["a"],
+            HashMap::from([(vec![0_u32], vec![(0_u32, 0_u32)])]),
+            vec![vec![0_u32]],
+        ))
+        .unwrap();
+        assert!(decode(&zero_weight).is_err());
+    }
+}
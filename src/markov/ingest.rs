@@ -0,0 +1,402 @@
+//! Turning Discord chat exports into a message dump `Markov::new` can learn from.
+//!
+//! Understands DiscordChatExporter's JSON and CSV exports,
+//! and the `messages.json`/`messages.csv` files from Discord's own data package.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use super::sanitize::{is_link, is_mention};
+use super::Markov;
+
+const USAGE: &str = "\
+usage: discord_bot_rust ingest [options] <export>...
+
+Reads DiscordChatExporter JSON/CSV exports or Discord data package messages.json/messages.csv files
+and writes their messages to a dump, one message per line.
+
+options:
+    --out <path>          where to write the dump (default: message-dump.txt)
+    --append              add to the dump instead of replacing it
+    --build               rebuild markov_data from the dump afterwards
+    --author <id|name>    only keep messages by this author, can be repeated
+                          (data packages don't record authors, so all their messages are kept)
+    --channel <id|name>   only keep messages from this channel, can be repeated
+                          (DiscordChatExporter CSVs get their channel from the file name)
+    --after <date>        only keep messages sent on or after this date (YYYY-MM-DD or RFC 3339)
+    --before <date>       only keep messages sent before this date (YYYY-MM-DD or RFC 3339)
+    --include-bots        keep messages sent by bots
+    --strip-links         remove links from messages
+    --strip-mentions      remove user, role and channel mentions, @everyone and @here from messages";
+
+/// A message from any of the supported export formats.
+/// Fields a format doesn't record are `None`.
+#[derive(Debug, Default)]
+struct Message {
+    author_id: Option<String>,
+    author_name: Option<String>,
+    is_bot: bool,
+    channel_id: Option<String>,
+    channel_name: Option<String>,
+    timestamp: Option<DateTime<Utc>>,
+    content: String,
+}
+
+/// Which messages end up in the dump, and how they're cleaned up.
+#[derive(Debug, Default)]
+struct Filter {
+    authors: Vec<String>,
+    channels: Vec<String>,
+    after: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+    include_bots: bool,
+    strip_links: bool,
+    strip_mentions: bool,
+}
+
+impl Filter {
+    /// Get the line to write for `message`, or `None` if it should be left out.
+    fn apply(&self, message: &Message) -> Option<String> {
+        if message.is_bot && !self.include_bots {
+            return None;
+        }
+        if !self.authors.is_empty()
+            && !matches_any(&self.authors, &message.author_id, &message.author_name)
+        {
+            return None;
+        }
+        if !self.channels.is_empty()
+            && !matches_any(&self.channels, &message.channel_id, &message.channel_name)
+        {
+            return None;
+        }
+        if self.after.is_some() || self.before.is_some() {
+            let timestamp = message.timestamp?;
+            if self.after.is_some_and(|after| timestamp < after)
+                || self.before.is_some_and(|before| timestamp >= before)
+            {
+                return None;
+            }
+        }
+
+        let line = message
+            .content
+            .split_whitespace()
+            .filter(|word| !(self.strip_links && is_link(word)))
+            .filter(|word| !(self.strip_mentions && is_mention(word)))
+            .collect::<Vec<&str>>()
+            .join(" ");
+        (!line.is_empty()).then_some(line)
+    }
+}
+
+/// Whether `id` or `name` is one of `wanted`. Names are compared case-insensitively.
+/// Formats that record neither always match, so filtering on them doesn't drop everything.
+fn matches_any(wanted: &[String], id: &Option<String>, name: &Option<String>) -> bool {
+    if id.is_none() && name.is_none() {
+        return true;
+    }
+    wanted.iter().any(|wanted| {
+        id.as_ref().is_some_and(|id| id == wanted)
+            || name
+                .as_ref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(wanted))
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExporterChannel {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExporterAuthor {
+    id: String,
+    name: String,
+    #[serde(default)]
+    is_bot: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExporterMessage {
+    timestamp: String,
+    content: String,
+    author: ExporterAuthor,
+}
+
+/// A DiscordChatExporter JSON export.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExporterExport {
+    channel: ExporterChannel,
+    messages: Vec<ExporterMessage>,
+}
+
+/// A message from a Discord data package's `messages.json`.
+#[derive(Deserialize)]
+struct PackageMessage {
+    #[serde(rename = "Timestamp")]
+    timestamp: String,
+    #[serde(rename = "Contents")]
+    contents: String,
+}
+
+/// Read every message in the export at `path`, working out its format from its contents.
+fn read_messages(path: &Path) -> Result<Vec<Message>> {
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+
+    if is_json {
+        let bytes = fs::read(path)?;
+        match bytes.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') => read_exporter_json(&bytes),
+            Some(b'[') => read_package_json(&bytes, path),
+            _ => bail!("{} is not a chat export", path.display()),
+        }
+    } else {
+        read_csv(path)
+    }
+}
+
+fn read_exporter_json(bytes: &[u8]) -> Result<Vec<Message>> {
+    let export: ExporterExport = serde_json::from_slice(bytes)?;
+
+    export
+        .messages
+        .into_iter()
+        .map(|message| {
+            Ok(Message {
+                author_id: Some(message.author.id),
+                author_name: Some(message.author.name),
+                is_bot: message.author.is_bot,
+                channel_id: Some(export.channel.id.clone()),
+                channel_name: Some(export.channel.name.clone()),
+                timestamp: Some(parse_timestamp(&message.timestamp)?),
+                content: message.content,
+            })
+        })
+        .collect()
+}
+
+fn read_package_json(bytes: &[u8], path: &Path) -> Result<Vec<Message>> {
+    let messages: Vec<PackageMessage> = serde_json::from_slice(bytes)?;
+    let channel_id = package_channel_id(path);
+
+    messages
+        .into_iter()
+        .map(|message| {
+            Ok(Message {
+                channel_id: channel_id.clone(),
+                timestamp: Some(parse_timestamp(&message.timestamp)?),
+                content: message.contents,
+                ..Message::default()
+            })
+        })
+        .collect()
+}
+
+fn read_csv(path: &Path) -> Result<Vec<Message>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|header| header == name);
+
+    let mut messages = Vec::new();
+    if let (Some(author_id), Some(author), Some(date), Some(content)) = (
+        column("AuthorID"),
+        column("Author"),
+        column("Date"),
+        column("Content"),
+    ) {
+        // DiscordChatExporter
+        let (channel_id, channel_name) = exporter_channel(path).unzip();
+        for record in reader.records() {
+            let record = record?;
+            messages.push(Message {
+                author_id: record.get(author_id).map(str::to_string),
+                author_name: record.get(author).map(str::to_string),
+                channel_id: channel_id.clone(),
+                channel_name: channel_name.clone(),
+                timestamp: record.get(date).map(parse_timestamp).transpose()?,
+                content: record.get(content).unwrap_or_default().to_string(),
+                ..Message::default()
+            });
+        }
+    } else if let (Some(timestamp), Some(contents)) = (column("Timestamp"), column("Contents")) {
+        // Discord data package
+        let channel_id = package_channel_id(path);
+        for record in reader.records() {
+            let record = record?;
+            messages.push(Message {
+                channel_id: channel_id.clone(),
+                timestamp: record.get(timestamp).map(parse_timestamp).transpose()?,
+                content: record.get(contents).unwrap_or_default().to_string(),
+                ..Message::default()
+            });
+        }
+    } else {
+        bail!("{} is not a chat export", path.display());
+    }
+
+    Ok(messages)
+}
+
+/// DiscordChatExporter names exports `<guild> - <category> - <channel> [<channel id>]` by default,
+/// which is the only place its CSVs say which channel they're from.
+fn exporter_channel(path: &Path) -> Option<(String, String)> {
+    let (name, id) = path
+        .file_stem()?
+        .to_str()?
+        .strip_suffix(']')?
+        .rsplit_once(" [")?;
+    let name = name.rsplit(" - ").next()?;
+    Some((id.to_string(), name.to_string()))
+}
+
+/// Data packages keep each channel's messages in a directory named `c<channel id>`.
+fn package_channel_id(path: &Path) -> Option<String> {
+    path.parent()?
+        .file_name()?
+        .to_str()?
+        .strip_prefix('c')
+        .map(str::to_string)
+}
+
+/// Parse a timestamp as written by any of the supported formats.
+fn parse_timestamp(timestamp: &str) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(timestamp) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    if let Ok(timestamp) = DateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%.f%:z") {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    if let Ok(timestamp) = NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%.f") {
+        return Ok(Utc.from_utc_datetime(&timestamp));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(timestamp, "%Y-%m-%d") {
+        return Ok(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()));
+    }
+
+    bail!("couldn't parse timestamp: {timestamp}")
+}
+
+/// Run the `ingest` subcommand with the arguments that follow it.
+pub fn run(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut filter = Filter::default();
+    let mut out = PathBuf::from("message-dump.txt");
+    let mut append = false;
+    let mut build = false;
+    let mut inputs: Vec<PathBuf> = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{arg} needs a value\n\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--out" => out = value()?.into(),
+            "--append" => append = true,
+            "--build" => build = true,
+            "--author" => filter.authors.push(value()?),
+            "--channel" => filter.channels.push(value()?),
+            "--after" => filter.after = Some(parse_timestamp(&value()?)?),
+            "--before" => filter.before = Some(parse_timestamp(&value()?)?),
+            "--include-bots" => filter.include_bots = true,
+            "--strip-links" => filter.strip_links = true,
+            "--strip-mentions" => filter.strip_mentions = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ if arg.starts_with("--") => bail!("unknown option {arg}\n\n{USAGE}"),
+            _ => inputs.push(arg.into()),
+        }
+    }
+    if inputs.is_empty() {
+        bail!("no exports given\n\n{USAGE}");
+    }
+
+    let file = File::options()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(&out)
+        .with_context(|| format!("couldn't open {}", out.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    for input in inputs {
+        let messages =
+            read_messages(&input).with_context(|| format!("couldn't read {}", input.display()))?;
+        let total = messages.len();
+        let mut kept = 0;
+        for line in messages.iter().filter_map(|message| filter.apply(message)) {
+            writeln!(writer, "{line}")?;
+            kept += 1;
+        }
+        eprintln!("{}: kept {kept} of {total} messages", input.display());
+    }
+    writer.flush()?;
+
+    if build {
+        let out = out
+            .to_str()
+            .ok_or_else(|| anyhow!("dump path must be valid UTF-8"))?;
+        Markov::new(2, out, false);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{exporter_channel, Filter, Message};
+
+    #[test]
+    fn exporter_channels() {
+        assert_eq!(
+            exporter_channel(Path::new(
+                "exports/Guild - Text Channels - general [123].csv"
+            )),
+            Some(("123".to_string(), "general".to_string()))
+        );
+        assert_eq!(
+            exporter_channel(Path::new("general [123].csv")),
+            Some(("123".to_string(), "general".to_string()))
+        );
+        assert_eq!(exporter_channel(Path::new("messages.csv")), None);
+    }
+
+    #[test]
+    fn unknown_fields_pass() {
+        let filter = Filter {
+            authors: vec!["someone".to_string()],
+            channels: vec!["general".to_string()],
+            ..Filter::default()
+        };
+        let message = |author_name: Option<&str>, channel_name: Option<&str>| Message {
+            author_name: author_name.map(str::to_string),
+            channel_name: channel_name.map(str::to_string),
+            content: "hello".to_string(),
+            ..Message::default()
+        };
+
+        assert!(filter.apply(&message(None, None)).is_some());
+        assert!(filter
+            .apply(&message(Some("Someone"), Some("general")))
+            .is_some());
+        assert!(filter.apply(&message(Some("else"), None)).is_none());
+        assert!(filter.apply(&message(None, Some("random"))).is_none());
+    }
+}
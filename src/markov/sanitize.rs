@@ -0,0 +1,183 @@
+//! Cleaning up generated text before it's sent, so it can't ping anyone it learned about.
+
+/// What happens to links in generated text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum LinkMode {
+    /// Leave links as they are.
+    #[default]
+    #[name = "Keep"]
+    Keep,
+    /// Remove links.
+    #[name = "Strip"]
+    Strip,
+    /// Break links so they aren't clickable, e.g. `hxxps://example[.]com`.
+    #[name = "Defang"]
+    Defang,
+}
+
+impl LinkMode {
+    /// The name `LinkMode` is stored as.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Keep => "keep",
+            Self::Strip => "strip",
+            Self::Defang => "defang",
+        }
+    }
+
+    /// Get the `LinkMode` stored as `name`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "keep" => Some(Self::Keep),
+            "strip" => Some(Self::Strip),
+            "defang" => Some(Self::Defang),
+            _ => None,
+        }
+    }
+}
+
+/// Neutralise mentions in `text` and handle its links according to `links`.
+///
+/// User and role mentions are removed, `@everyone` and `@here` are broken with a zero-width space.
+/// Never returns an empty string, as Discord won't send an empty message.
+pub fn sanitize(text: &str, links: LinkMode) -> String {
+    let sanitized = text
+        .split_whitespace()
+        .filter_map(|word| {
+            if is_link(word) {
+                return match links {
+                    LinkMode::Keep => Some(word.to_string()),
+                    LinkMode::Strip => None,
+                    LinkMode::Defang => Some(defang(word)),
+                };
+            }
+
+            let word = remove_mentions(word)
+                .replace("@everyone", "@\u{200B}everyone")
+                .replace("@here", "@\u{200B}here");
+            (!word.is_empty()).then_some(word)
+        })
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    if sanitized.is_empty() {
+        "...".to_string()
+    } else {
+        sanitized
+    }
+}
+
+/// Whether `word` is a link.
+pub fn is_link(word: &str) -> bool {
+    let word = word.trim_start_matches(['<', '(']);
+    word.starts_with("http://") || word.starts_with("https://")
+}
+
+/// Whether `word` is a user, role or channel mention, or pings everyone.
+pub fn is_mention(word: &str) -> bool {
+    word.contains("@everyone")
+        || word.contains("@here")
+        || ["<@", "<#"]
+            .iter()
+            .any(|start| word.find(start).is_some_and(|i| word[i..].contains('>')))
+}
+
+/// Remove every `<@id>`, `<@!id>` and `<@&id>` in `word`.
+fn remove_mentions(word: &str) -> String {
+    let mut out = String::with_capacity(word.len());
+    let mut rest = word;
+
+    while let Some(start) = rest.find("<@") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let id = after.strip_prefix(['!', '&']).unwrap_or(after);
+        let digits = id.len()
+            - id.trim_start_matches(|char: char| char.is_ascii_digit())
+                .len();
+
+        match id[digits..].strip_prefix('>') {
+            Some(remaining) if digits > 0 => rest = remaining,
+            _ => {
+                out.push_str("<@");
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+/// Make the link in `word` unclickable by changing its scheme and bracketing the dots in its host.
+fn defang(word: &str) -> String {
+    let Some(scheme_end) = word.find("://") else {
+        return word.to_string();
+    };
+    let host_start = scheme_end + 3;
+    let host_end = word[host_start..]
+        .find(['/', '?', '#', '>', ')'])
+        .map_or(word.len(), |i| host_start + i);
+
+    format!(
+        "{}://{}{}",
+        word[..scheme_end].replacen("http", "hxxp", 1),
+        word[host_start..host_end].replace('.', "[.]"),
+        &word[host_end..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{defang, is_mention, remove_mentions, sanitize, LinkMode};
+
+    #[test]
+    fn mentions() {
+        assert_eq!(remove_mentions("<@123>"), "");
+        assert_eq!(remove_mentions("hi<@!123>!"), "hi!");
+        assert_eq!(remove_mentions("<@&123><@456>"), "");
+        // not mentions, so left alone
+        assert_eq!(remove_mentions("<@>"), "<@>");
+        assert_eq!(remove_mentions("<@abc>"), "<@abc>");
+        assert_eq!(remove_mentions("<@123"), "<@123");
+
+        assert_eq!(
+            sanitize("hello <@123> and <@!456>", LinkMode::Keep),
+            "hello and"
+        );
+        assert_eq!(sanitize("<@&789>", LinkMode::Keep), "...");
+        // channel mentions don't ping anyone
+        assert_eq!(sanitize("see <#123>", LinkMode::Keep), "see <#123>");
+        assert!(["<@1>", "<@!1>", "<@&1>", "<#1>", "@everyone", "x@here"]
+            .iter()
+            .all(|word| is_mention(word)));
+        assert!(!is_mention("<#"));
+    }
+
+    #[test]
+    fn everyone() {
+        assert_eq!(
+            sanitize("@everyone look", LinkMode::Keep),
+            "@\u{200B}everyone look"
+        );
+        assert_eq!(sanitize("(@here)", LinkMode::Keep), "(@\u{200B}here)");
+    }
+
+    #[test]
+    fn links() {
+        let text = "look https://example.com/a.b?c=d.e now";
+        assert_eq!(sanitize(text, LinkMode::Keep), text);
+        assert_eq!(sanitize(text, LinkMode::Strip), "look now");
+        assert_eq!(
+            sanitize(text, LinkMode::Defang),
+            "look hxxps://example[.]com/a.b?c=d.e now"
+        );
+        assert_eq!(sanitize("https://example.com", LinkMode::Strip), "...");
+
+        assert_eq!(defang("<http://a.b.c>"), "<hxxp://a[.]b[.]c>");
+        assert_eq!(defang("(https://a.b)"), "(hxxps://a[.]b)");
+        assert_eq!(defang("example.com"), "example.com");
+        for mode in [LinkMode::Keep, LinkMode::Strip, LinkMode::Defang] {
+            assert_eq!(LinkMode::parse(mode.as_str()), Some(mode));
+        }
+    }
+}
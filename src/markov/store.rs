@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use log::error;
+use tokio::sync::{Mutex, RwLock};
+
+use super::Markov;
+
+/// Directory per-guild and per-user models are saved in.
+const MODEL_DIR: &str = "markov";
+
+/// Which messages a model is trained on.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ModelKey {
+    Guild(u64),
+    /// What a user has said in one guild, so imitating them doesn't leak what they said elsewhere.
+    User {
+        guild_id: u64,
+        user_id: u64,
+    },
+}
+
+impl ModelKey {
+    fn path(&self) -> PathBuf {
+        let file_name = match self {
+            Self::Guild(id) => format!("guild_{id}"),
+            Self::User { guild_id, user_id } => format!("user_{guild_id}_{user_id}"),
+        };
+        PathBuf::from(MODEL_DIR).join(file_name)
+    }
+}
+
+#[derive(Debug)]
+pub struct MarkovStore {
+    key_size: u8,
+    global: Arc<RwLock<Markov>>,
+    models: Mutex<HashMap<ModelKey, Arc<RwLock<Markov>>>>,
+    /// Users who have opted out since starting, whose models mustn't be kept or saved again
+    /// by messages learned while they were opting out.
+    opted_out: Mutex<HashSet<u64>>,
+}
+
+impl MarkovStore {
+    pub fn new(global: Markov) -> Self {
+        Self {
+            key_size: global.key_size,
+            global: Arc::new(RwLock::new(global)),
+            models: Mutex::new(HashMap::new()),
+            opted_out: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// The model built from the message dump.
+    pub fn global(&self) -> Arc<RwLock<Markov>> {
+        self.global.clone()
+    }
+
+    /// Get the model for `key`, loading it from disk the first time it is asked for.
+    /// A user who has opted out gets a model that is never kept.
+    pub async fn get(&self, key: ModelKey) -> Arc<RwLock<Markov>> {
+        if let Some(markov) = self.models.lock().await.get(&key) {
+            return markov.clone();
+        }
+
+        // load without holding the lock so other models can be used in the meantime
+        let key_size = self.key_size;
+        let markov = tokio::task::spawn_blocking(move || Markov::load(key_size, key.path()))
+            .await
+            .expect("loading markov data panicked");
+
+        let opted_out = self.opted_out.lock().await;
+        if matches!(key, ModelKey::User { user_id, .. } if opted_out.contains(&user_id)) {
+            return Arc::new(RwLock::new(markov));
+        }
+        // if it was loaded twice at once, keep whichever got here first
+        self.models
+            .lock()
+            .await
+            .entry(key)
+            .or_insert_with(|| Arc::new(RwLock::new(markov)))
+            .clone()
+    }
+
+    /// Get the model to talk with in guild with `guild_id`,
+    /// falling back to the global model until the guild's own has learned something.
+    pub async fn for_guild(&self, guild_id: Option<u64>) -> Arc<RwLock<Markov>> {
+        if let Some(guild_id) = guild_id {
+            let markov = self.get(ModelKey::Guild(guild_id)).await;
+            if !markov.read().await.is_empty() {
+                return markov;
+            }
+        }
+
+        self.global()
+    }
+
+    /// Forget every model of user with `user_id`, in every guild, and delete them from disk.
+    pub async fn remove_user(&self, user_id: u64) {
+        // held until the files are gone so a model being saved at the same time can't write one back
+        let mut opted_out = self.opted_out.lock().await;
+        opted_out.insert(user_id);
+        self.models
+            .lock()
+            .await
+            .retain(|key, _| !matches!(key, ModelKey::User { user_id: id, .. } if *id == user_id));
+
+        let entries = match fs::read_dir(MODEL_DIR) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return,
+            Err(err) => {
+                error!("couldn't read markov data directory: {err}");
+                return;
+            }
+        };
+        let suffix = format!("_{user_id}");
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            if file_name.starts_with("user_") && file_name.ends_with(&suffix) {
+                if let Err(err) = fs::remove_file(entry.path()) {
+                    error!("couldn't remove markov data {file_name}: {err}");
+                }
+            }
+        }
+    }
+
+    /// Let user with `user_id` have models again after opting out.
+    pub async fn add_user(&self, user_id: u64) {
+        self.opted_out.lock().await.remove(&user_id);
+    }
+
+    /// Write every model that has learned something since it was last saved.
+    pub async fn save_dirty(&self) {
+        // held while saving so a user opting out can't have their files deleted and then written back
+        let _opted_out = self.opted_out.lock().await;
+        let mut models = vec![self.global()];
+        models.extend(self.models.lock().await.values().cloned());
+
+        for markov in models {
+            // encode while reading, then write without the lock so learning doesn't wait on the disk
+            let snapshot = {
+                let markov = markov.read().await;
+                if !markov.is_dirty() {
+                    continue;
+                }
+                markov.snapshot()
+            };
+            let Some(snapshot) = snapshot else {
+                continue;
+            };
+            let written = tokio::task::spawn_blocking(move || snapshot.write())
+                .await
+                .expect("saving markov data panicked");
+            if let Err(err) = written {
+                error!("couldn't write markov data: {err}");
+                markov.read().await.mark_dirty();
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::env;
+use std::sync::{Mutex, PoisonError};
+
+use anyhow::Result;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Where everything random the bot does gets its randomness.
+///
+/// Each use is seeded from here, so anything random can be made again from its seed with `RngSource::rng`.
+/// ChaCha8 is used rather than `StdRng`, whose algorithm can change between `rand` versions,
+/// so saved seeds keep rolling the same dice.
+#[derive(Debug)]
+pub struct RngSource {
+    seeds: Mutex<ChaCha8Rng>,
+}
+
+impl RngSource {
+    /// A source that gives out different seeds every time the bot starts.
+    pub fn from_entropy() -> Self {
+        Self {
+            seeds: Mutex::new(ChaCha8Rng::from_entropy()),
+        }
+    }
+
+    /// A source that always gives out the same seeds in the same order.
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            seeds: Mutex::new(ChaCha8Rng::seed_from_u64(seed)),
+        }
+    }
+
+    /// A source seeded with `RNG_SEED` if it's set, or from entropy otherwise.
+    pub fn from_env() -> Result<Self> {
+        Ok(match env::var("RNG_SEED") {
+            Ok(seed) => Self::seeded(seed.parse()?),
+            Err(_) => Self::from_entropy(),
+        })
+    }
+
+    /// A new seed, short enough to show to users.
+    pub fn seed(&self) -> u32 {
+        self.seeds
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .gen()
+    }
+
+    /// An RNG that makes the same choices every time it's made with `seed`.
+    pub fn rng(seed: u32) -> ChaCha8Rng {
+        ChaCha8Rng::seed_from_u64(seed.into())
+    }
+
+    /// An RNG made with a new seed.
+    pub fn next(&self) -> ChaCha8Rng {
+        Self::rng(self.seed())
+    }
+}